@@ -0,0 +1,298 @@
+//! # Command-line interface
+//! 
+//! `rlox <command> [input]`, where the input is a file path, `-` for stdin,
+//! or `-e '<code>'` for a one-liner.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use crate::lox::{Lox, EX_USAGE, EX_DATAERR, EX_NOINPUT, EX_IOERR};
+use crate::loxerror::{get_error, LoxError};
+use crate::scanner::Scanner;
+use crate::parser::Parser;
+use crate::formatter;
+use crate::lsp;
+use crate::debugger;
+use crate::dap;
+use crate::profiler;
+use crate::coverage;
+use crate::testrunner;
+use crate::fuzz;
+
+pub const USAGE: &str = "\
+Usage: rlox [command] [input]
+
+Commands:
+    run [--profile | --coverage] [--output <path>] <input>
+                      Run a script (the default when a file is given). With
+                      --profile, report where the time went on stderr and
+                      write folded stacks to --output, or rlox.folded. With
+                      --coverage, print the source annotated with hit counts
+                      on stderr and write an lcov report to --output, or
+                      lcov.info
+    repl              Start an interactive session (the default with no arguments)
+    tokens <input>    Print the tokens the scanner produces
+    ast <input>       Print the syntax tree the parser produces
+    check <input>     Report every scanning and parsing error without running
+    fmt [--check] <input>
+                      Print the program in canonical style. With --check,
+                      print nothing and fail if it isn't formatted already
+    test <path>       Run every .lox script under a directory and check what it
+                      does against its `// expect: ...` comments
+    fuzz [runs] [seed]
+                      Run randomly generated programs, 1000 by default,
+                      and report any that make the interpreter panic
+    debug <file>      Run a script under the interactive debugger
+    dap               Start a debug adapter on stdin and stdout
+    lsp               Start a language server on stdin and stdout
+    help              Show this message
+
+Input is a file path, `-` to read from stdin, or `-e <code>` to pass code directly.";
+
+/// Where a command reads its program from.
+pub enum Input {
+    File(String),
+    Stdin,
+    Code(String),
+}
+
+impl Input {
+    /// Parses the arguments that follow the command name.
+    pub fn from_args(args: &[String]) -> Result<Input, String> {
+        match args {
+            [flag, code] if flag == "-e" => Ok(Input::Code(code.clone())),
+            [flag] if flag == "-e" => Err(String::from("-e needs some code to run")),
+            [dash] if dash == "-" => Ok(Input::Stdin),
+            [path] if !path.starts_with('-') => Ok(Input::File(path.clone())),
+            [arg] => Err(format!("Unknown option {}", arg)),
+            [] => Err(String::from("Missing input")),
+            _ => Err(String::from("Too many arguments")),
+        }
+    }
+
+    /// What to call the input in messages and reports.
+    pub fn name(&self) -> String {
+        match self {
+            Input::File(path) => path.clone(),
+            Input::Stdin => String::from("<stdin>"),
+            Input::Code(_) => String::from("<code>"),
+        }
+    }
+
+    pub fn read(&self) -> Result<String, LoxError> {
+        match self {
+            Input::File(path) => fs::read_to_string(path)
+                .map_err(|e| LoxError::new(&format!("Could not read file {}: {}", path, e))),
+            Input::Stdin => {
+                let mut source = String::new();
+
+                io::stdin().read_to_string(&mut source)
+                    .map_err(|e| LoxError::new(&format!("Could not read stdin: {}", e)))?;
+
+                Ok(source)
+            },
+            Input::Code(code) => Ok(code.clone()),
+        }
+    }
+}
+
+/// Runs the command line `args` (without the program name) and returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let (command, rest) = match args.split_first() {
+        None => return repl(),
+        Some((c, rest)) => (c.as_str(), rest),
+    };
+
+    let action: &dyn Fn(&str) -> i32 = match command {
+        "repl" if rest.is_empty() => return repl(),
+        "repl" => return usage_error("repl takes no arguments"),
+        "debug" => return match rest {
+            [path] if !path.starts_with('-') => debugger::run(path),
+            _ => usage_error("debug needs the path of a script"),
+        },
+        "test" => return match rest {
+            [path] if !path.starts_with('-') => testrunner::run(path),
+            _ => usage_error("test needs a directory or a script"),
+        },
+        "fuzz" => {
+            let numbers: Result<Vec<u64>, _> = rest.iter().map(|a| a.parse::<u64>()).collect();
+
+            return match numbers.as_deref() {
+                Ok([]) => fuzz::run(1000, 0),
+                Ok([runs]) => fuzz::run(*runs, 0),
+                Ok([runs, seed]) => fuzz::run(*runs, *seed),
+                _ => usage_error("fuzz takes a number of runs and a seed"),
+            };
+        },
+        "dap" if rest.is_empty() => return dap::run(),
+        "dap" => return usage_error("dap takes no arguments"),
+        "lsp" if rest.is_empty() => return lsp::run(),
+        "lsp" => return usage_error("lsp takes no arguments"),
+        "help" | "--help" | "-h" => return write_stdout(|out| writeln!(out, "{}", USAGE)),
+        "run" => {
+            // Like `fmt --check`, the flags may come before or after the input
+            let profiling = rest.iter().any(|a| a == "--profile");
+            let covering = rest.iter().any(|a| a == "--coverage");
+            let mut rest: Vec<String> = rest.iter().filter(|a| *a != "--profile" && *a != "--coverage").cloned().collect();
+
+            let output = match rest.iter().position(|a| a == "--output") {
+                Some(i) if i + 1 < rest.len() => {
+                    let path = rest.remove(i + 1);
+                    rest.remove(i);
+                    Some(path)
+                },
+                Some(_) => return usage_error("--output needs a path"),
+                None => None,
+            };
+
+            if profiling && covering {
+                return usage_error("--profile and --coverage can't be used together");
+            }
+
+            if output.is_some() && !covering && !profiling {
+                return usage_error("--output needs --profile or --coverage");
+            }
+
+            if covering {
+                let lcov_path = output.unwrap_or_else(|| String::from(coverage::LCOV_PATH));
+
+                // The reports name the file, so this needs more than the source
+                return match Input::from_args(&rest) {
+                    Ok(input) => with_input(&rest, &|source| coverage::run(&input.name(), source, &lcov_path)),
+                    Err(msg) => usage_error(&msg),
+                };
+            }
+
+            if profiling {
+                let folded_path = output.unwrap_or_else(|| String::from(profiler::FOLDED_PATH));
+                return with_input(&rest, &|source| profiler::run(source, &folded_path));
+            }
+
+            return with_input(&rest, &Lox::run_source);
+        },
+        "tokens" => &tokens,
+        "ast" => &ast,
+        "check" => &check,
+        "fmt" => {
+            // `--check` may come before or after the input
+            let checking = rest.iter().any(|a| a == "--check");
+            let rest: Vec<String> = rest.iter().filter(|a| *a != "--check").cloned().collect();
+
+            return with_input(&rest, &|source| fmt(source, checking));
+        },
+        // `rlox script.lox` and `rlox -e '<code>'` are shorthand for `rlox run ...`
+        _ if !rest.is_empty() && command != "-e" => return usage_error(&format!("Unknown command {}", command)),
+        _ => return with_input(args, &Lox::run_source),
+    };
+
+    with_input(rest, action)
+}
+
+fn with_input(args: &[String], action: &dyn Fn(&str) -> i32) -> i32 {
+    let input = match Input::from_args(args) {
+        Ok(i) => i,
+        Err(msg) => return usage_error(&msg),
+    };
+
+    match input.read() {
+        Ok(source) => action(&source),
+        Err(e) => {
+            eprintln!("{}", e);
+            EX_NOINPUT
+        }
+    }
+}
+
+fn usage_error(msg: &str) -> i32 {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    EX_USAGE
+}
+
+fn repl() -> i32 {
+    match Lox::run_prompt() {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            EX_IOERR
+        }
+    }
+}
+
+/// Writes to stdout with `write` and returns the exit code. A reader that stops
+/// early, like `head`, has all it wanted, so a broken pipe isn't a failure.
+fn write_stdout(write: impl FnOnce(&mut io::StdoutLock) -> io::Result<()>) -> i32 {
+    let mut out = io::stdout().lock();
+
+    match write(&mut out).and_then(|_| out.flush()) {
+        Ok(_) => 0,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(e) => {
+            eprintln!("Could not write output: {}", e);
+            EX_IOERR
+        }
+    }
+}
+
+fn tokens(source: &str) -> i32 {
+    Lox::clear_errors();
+
+    let code = write_stdout(|out| {
+        for token in Scanner::new(source).reporting() {
+            writeln!(out, "{}", token)?;
+        }
+
+        Ok(())
+    });
+
+    if code != 0 { return code; }
+
+    if get_error() { EX_DATAERR } else { 0 }
+}
+
+fn ast(source: &str) -> i32 {
+    match Lox::parse(source) {
+        Some(statements) => write_stdout(|out| {
+            for statement in statements {
+                writeln!(out, "{}", statement)?;
+            }
+
+            Ok(())
+        }),
+        None => EX_DATAERR,
+    }
+}
+
+fn check(source: &str) -> i32 {
+    match Lox::parse(source) {
+        Some(_) => 0,
+        None => EX_DATAERR,
+    }
+}
+
+/// Prints `source` in canonical style. With `check`, prints nothing and
+/// fails if the source isn't already formatted that way.
+fn fmt(source: &str, check: bool) -> i32 {
+    Lox::clear_errors();
+
+    let (tokens, comments) = Scanner::new(source).scan_tokens_and_comments();
+
+    let statements = match Parser::new(tokens).parse_with_lines() {
+        Ok(statements) if !get_error() => statements,
+        Ok(_) => return EX_DATAERR,
+        Err(errors) => {
+            eprintln!("{}", LoxError::from(errors));
+            return EX_DATAERR;
+        }
+    };
+
+    let formatted = formatter::format_program(&statements, &comments);
+
+    if !check {
+        write_stdout(|out| out.write_all(formatted.as_bytes()))
+    // Formatted output never has a byte order mark, but having one isn't a style issue
+    } else if formatted == source.trim_start_matches('\u{FEFF}') {
+        0
+    } else {
+        eprintln!("Input is not formatted. Run rlox fmt to see the expected formatting.");
+        1
+    }
+}
@@ -0,0 +1,256 @@
+//! # Lox Coverage
+//!
+//! Line and branch coverage for `rlox run --coverage`. The coverage hook counts
+//! the statements that run on each line and which way each branch goes. Lines
+//! with a statement on them that never ran are the ones a test suite misses.
+//!
+//! Results are written in lcov's tracefile format, which coverage viewers and
+//! CI services read, and as annotated source with a hit count beside each line.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::rc::Rc;
+use crate::expr::{Expr, InterpolationPart};
+use crate::interpreter::{Hook, Interpreter, RuntimeError};
+use crate::lox::{Lox, EX_DATAERR, EX_IOERR, EX_SOFTWARE};
+use crate::loxerror::LoxError;
+use crate::stmt::Stmt;
+use crate::token::Token;
+
+/// Where the lcov tracefile is written unless we're told otherwise.
+pub const LCOV_PATH: &str = "lcov.info";
+
+/// Runs `source`, read from `file`, and reports what it covered, writing the
+/// lcov tracefile to `lcov_path`. Returns the exit code the process should use.
+pub fn run(file: &str, source: &str, lcov_path: &str) -> i32 {
+    let statements = match Lox::parse(source) {
+        Some(statements) => statements,
+        None => return EX_DATAERR,
+    };
+
+    let coverage = Rc::new(RefCell::new(Coverage::default()));
+    coverage.borrow_mut().add_file(file, source, &statements);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(Box::new(CoverageHook::new(&coverage, file)));
+
+    let mut code = match interpreter.interpret(&statements) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", LoxError::from(e));
+            EX_SOFTWARE
+        }
+    };
+
+    let coverage = coverage.borrow();
+
+    eprint!("\n{}", coverage.annotated());
+
+    match fs::write(lcov_path, coverage.lcov()) {
+        Ok(_) => eprintln!("lcov report written to {}", lcov_path),
+        Err(e) => {
+            eprintln!("Could not write {}: {}", lcov_path, e);
+            code = EX_IOERR;
+        }
+    }
+
+    code
+}
+
+/// Coverage for every file a run loaded.
+#[derive(Default)]
+pub struct Coverage {
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+pub struct FileCoverage {
+    source: Vec<String>,
+    /// How many statements ran on each line that has one
+    pub lines: BTreeMap<usize, usize>,
+    /// How often each branch went each way, keyed by the line and column of the
+    /// token that decides it. The first count is for the branch being taken.
+    /// Both are 0 for a branch that was never reached.
+    pub branches: BTreeMap<(usize, usize), [usize; 2]>,
+}
+
+impl FileCoverage {
+    /// Lines with statements on them that never ran.
+    pub fn missed(&self) -> Vec<usize> {
+        self.lines.iter().filter(|(_, hits)| **hits == 0).map(|(line, _)| *line).collect()
+    }
+}
+
+impl Coverage {
+    /// Starts tracking `file`, with every line that has a statement on it and
+    /// every branch in it not yet run.
+    pub fn add_file(&mut self, file: &str, source: &str, statements: &[Stmt]) {
+        let lines = statements.iter().map(|s| (s.line(), 0)).collect();
+        let mut branches = BTreeMap::new();
+
+        for statement in statements {
+            let expression = match statement {
+                Stmt::Expression(e) => Some(&e.expression),
+                Stmt::Print(p) => Some(&p.expression),
+                Stmt::Var(v) => v.initializer.as_ref(),
+            };
+
+            if let Some(e) = expression {
+                add_branches(e, &mut branches);
+            }
+        }
+
+        self.files.insert(String::from(file), FileCoverage {
+            source: source.trim_start_matches('\u{FEFF}').lines().map(String::from).collect(),
+            lines,
+            branches,
+        });
+    }
+
+    /// The lcov tracefile for every file.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::new();
+
+        for (name, file) in &self.files {
+            lcov.push_str(&format!("SF:{}\n", name));
+
+            // lcov numbers the branches from each line's first block
+            let mut block = 0;
+            let mut previous_line = 0;
+
+            for ((line, _), taken) in &file.branches {
+                if *line != previous_line {
+                    block = 0;
+                    previous_line = *line;
+                }
+
+                // lcov writes `-` for branches whose condition never ran
+                let reached = taken.iter().any(|c| *c > 0);
+
+                for (branch, count) in taken.iter().enumerate() {
+                    let count = if reached { count.to_string() } else { String::from("-") };
+                    lcov.push_str(&format!("BRDA:{},{},{},{}\n", line, block, branch, count));
+                }
+
+                block += 1;
+            }
+
+            let branches = file.branches.len() * 2;
+            let branches_hit = file.branches.values().flatten().filter(|c| **c > 0).count();
+
+            lcov.push_str(&format!("BRF:{}\nBRH:{}\n", branches, branches_hit));
+
+            for (line, hits) in &file.lines {
+                lcov.push_str(&format!("DA:{},{}\n", line, hits));
+            }
+
+            let lines_hit = file.lines.values().filter(|h| **h > 0).count();
+
+            lcov.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", file.lines.len(), lines_hit));
+        }
+
+        lcov
+    }
+
+    /// Each file's source with hit counts beside the lines that have statements
+    /// and `#####` beside the ones that never ran, gcov-style, then a summary.
+    pub fn annotated(&self) -> String {
+        let mut report = String::new();
+
+        for (name, file) in &self.files {
+            report.push_str(&format!("{}\n", name));
+
+            for (i, text) in file.source.iter().enumerate() {
+                let count = match file.lines.get(&(i + 1)) {
+                    Some(0) => String::from("#####"),
+                    Some(hits) => hits.to_string(),
+                    None => String::from("-"),
+                };
+
+                report.push_str(&format!("{:>9}:{:>5}: {}\n", count, i + 1, text));
+            }
+
+            let covered = file.lines.len() - file.missed().len();
+            let percent = if file.lines.is_empty() { 100.0 } else { 100.0 * covered as f64 / file.lines.len() as f64 };
+
+            report.push_str(&format!("\nLines: {}/{} ({:.1}%)\n", covered, file.lines.len(), percent));
+
+            if !file.branches.is_empty() {
+                let hit = file.branches.values().flatten().filter(|c| **c > 0).count();
+                report.push_str(&format!("Branches: {}/{}\n", hit, file.branches.len() * 2));
+            }
+
+            let missed: Vec<String> = file.missed().iter().map(|l| l.to_string()).collect();
+
+            if !missed.is_empty() {
+                report.push_str(&format!("Never executed: {}\n", missed.join(", ")));
+            }
+
+            report.push('\n');
+        }
+
+        report
+    }
+}
+
+/// Adds every conditional in `expr` to `branches`, not yet taken either way.
+fn add_branches(expr: &Expr, branches: &mut BTreeMap<(usize, usize), [usize; 2]>) {
+    match expr {
+        Expr::Conditional(c) => {
+            branches.insert((c.question.line, c.question.column), [0, 0]);
+            add_branches(&c.condition, branches);
+            add_branches(&c.then_branch, branches);
+            add_branches(&c.else_branch, branches);
+        },
+        Expr::Binary(b) => {
+            add_branches(&b.left, branches);
+            add_branches(&b.right, branches);
+        },
+        Expr::Comma(c) => {
+            add_branches(&c.left, branches);
+            add_branches(&c.right, branches);
+        },
+        Expr::Unary(u) => add_branches(&u.operand, branches),
+        Expr::Grouping(g) => add_branches(&g.0, branches),
+        Expr::Assign(a) => add_branches(&a.value, branches),
+        Expr::Interpolation(i) => {
+            for part in &i.parts {
+                if let InterpolationPart::Expr(e) = part {
+                    add_branches(e, branches);
+                }
+            }
+        },
+        Expr::Literal(_) | Expr::Variable(_) => {},
+    }
+}
+
+/// Records what runs in one file.
+pub struct CoverageHook {
+    coverage: Rc<RefCell<Coverage>>,
+    file: String,
+}
+
+impl CoverageHook {
+    pub fn new(coverage: &Rc<RefCell<Coverage>>, file: &str) -> Self {
+        CoverageHook { coverage: Rc::clone(coverage), file: String::from(file) }
+    }
+}
+
+impl Hook for CoverageHook {
+    fn before_statement(&mut self, _interpreter: &mut Interpreter, statement: &Stmt) -> Result<(), RuntimeError> {
+        if let Some(file) = self.coverage.borrow_mut().files.get_mut(&self.file) {
+            *file.lines.entry(statement.line()).or_default() += 1;
+        }
+
+        Ok(())
+    }
+
+    fn branch(&mut self, _interpreter: &mut Interpreter, token: &Token, taken: bool) -> Result<(), RuntimeError> {
+        if let Some(file) = self.coverage.borrow_mut().files.get_mut(&self.file) {
+            let counts = file.branches.entry((token.line, token.column)).or_default();
+            counts[if taken { 0 } else { 1 }] += 1;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,442 @@
+//! # Lox Concrete Syntax Tree
+//!
+//! The parser throws away everything that doesn't change what a program means.
+//! Tools that rewrite source, like formatters and refactorings, need the rest
+//! too. Here the whitespace and comments around each token are kept as trivia,
+//! and the tokens are arranged in a tree whose text is exactly the source it was
+//! built from, byte for byte. Even source with errors in it round-trips.
+
+use std::fmt;
+use crate::parser::MAX_NESTING;
+use crate::scanner::Scanner;
+use crate::token::{Token, TokenType, TokenType::*};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    // Spaces, tabs and carriage returns
+    Whitespace,
+    Newline,
+    Comment,
+    // Characters the scanner couldn't make a token out of
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+/// A token plus the trivia around it. A token owns the trivia that follows it
+/// up to the end of its line; everything else belongs to the token after it.
+#[derive(Debug, Clone)]
+pub struct SyntaxToken<'a> {
+    pub leading: Vec<Trivia>,
+    pub token: Token<'a>,
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Program,
+    VarDecl,
+    PrintStmt,
+    ExprStmt,
+    Binary,
+    Unary,
+    Grouping,
+    Literal,
+    Variable,
+    Assign,
+    Conditional,
+    Comma,
+    // Tokens that don't fit the grammar where they appear
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement<'a> {
+    Node(SyntaxNode<'a>),
+    Token(SyntaxToken<'a>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxNode<'a> {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement<'a>>,
+}
+
+impl<'a> SyntaxNode<'a> {
+    fn new(kind: NodeKind, children: Vec<SyntaxElement<'a>>) -> Self {
+        SyntaxNode { kind, children }
+    }
+
+    /// Every token under this node, in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken<'a>> {
+        let mut tokens = Vec::new();
+
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(n) => tokens.extend(n.tokens()),
+                SyntaxElement::Token(t) => tokens.push(t),
+            }
+        }
+
+        tokens
+    }
+}
+
+/// Scans `source` into tokens that keep their surrounding trivia.
+/// The last token is always EOF, which holds whatever trails the program.
+pub fn tokenize(source: &str) -> Vec<SyntaxToken<'_>> {
+    let mut tokens: Vec<SyntaxToken> = Vec::new();
+    // Where the previous token ended in bytes, after any byte order mark
+    let bom = if source.starts_with('\u{FEFF}') { '\u{FEFF}'.len_utf8() } else { 0 };
+    let mut end = bom;
+
+    for (token, span) in Scanner::new(source).scan_tokens_with_spans() {
+        let mut leading = split_trivia(&source[end..span.start]);
+
+        if tokens.is_empty() && bom > 0 {
+            leading.insert(0, Trivia { kind: TriviaKind::Whitespace, text: String::from("\u{FEFF}") });
+        }
+
+        if let Some(previous) = tokens.last_mut() {
+            let line_end = leading.iter()
+                .position(|t| t.kind == TriviaKind::Newline)
+                .unwrap_or(leading.len());
+
+            previous.trailing = leading.drain(..line_end).collect();
+        }
+
+        tokens.push(SyntaxToken { leading, token, trailing: Vec::new() });
+        end = span.end;
+    }
+
+    tokens
+}
+
+/// Parses `source` into a lossless tree. This never fails: anything that
+/// doesn't fit the grammar ends up in an `Error` node.
+pub fn parse(source: &str) -> SyntaxNode<'_> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+
+    CstParser { tokens, depth: 0 }.program()
+}
+
+fn split_trivia(text: &str) -> Vec<Trivia> {
+    let mut trivia: Vec<Trivia> = Vec::new();
+    // In bytes
+    let mut i = 0;
+
+    while i < text.len() {
+        let start = i;
+        let rest = &text[i..];
+        let mut chars = rest.chars();
+
+        let kind = match (chars.next(), chars.next()) {
+            (Some('\n'), _) => {
+                i += 1;
+                TriviaKind::Newline
+            },
+            (Some(c), _) if Scanner::is_whitespace(c) => {
+                i += rest.find(|c| !Scanner::is_whitespace(c)).unwrap_or(rest.len());
+                TriviaKind::Whitespace
+            },
+            (Some('/'), Some('/')) => {
+                i += rest.find('\n').unwrap_or(rest.len());
+                TriviaKind::Comment
+            },
+            (Some('/'), Some('*')) => {
+                // Block comments nest, and may run to the end of the source.
+                // Their delimiters are ASCII, so stepping over bytes is safe.
+                let bytes = text.as_bytes();
+                let mut depth = 0;
+
+                loop {
+                    match (bytes.get(i), bytes.get(i + 1)) {
+                        (Some(b'/'), Some(b'*')) => { depth += 1; i += 2; },
+                        (Some(b'*'), Some(b'/')) => { depth -= 1; i += 2; },
+                        (Some(_), _) => i += 1,
+                        (None, _) => break,
+                    }
+
+                    if depth == 0 { break; }
+                }
+
+                TriviaKind::Comment
+            },
+            (c, _) => {
+                i += c.map_or(1, char::len_utf8);
+                TriviaKind::Skipped
+            }
+        };
+
+        let text = &text[start..i];
+
+        match trivia.last_mut() {
+            Some(last) if kind == TriviaKind::Skipped && last.kind == TriviaKind::Skipped => last.text.push_str(text),
+            _ => trivia.push(Trivia { kind, text: String::from(text) }),
+        }
+    }
+
+    trivia
+}
+
+/// Mirrors the grammar `Parser` understands, but keeps every token and
+/// recovers from errors instead of stopping. Expressions nest no deeper than
+/// `Parser` allows either, counted the same way.
+struct CstParser<'a> {
+    // Reversed, so the next token is at the end
+    tokens: Vec<SyntaxToken<'a>>,
+    depth: usize,
+}
+
+impl<'a> CstParser<'a> {
+    fn program(&mut self) -> SyntaxNode<'a> {
+        let mut children = Vec::new();
+
+        while !self.check(&EOF) && self.peek().is_some() {
+            children.push(SyntaxElement::Node(self.declaration()));
+        }
+
+        children.extend(self.bump());
+
+        SyntaxNode::new(NodeKind::Program, children)
+    }
+
+    fn declaration(&mut self) -> SyntaxNode<'a> {
+        if !self.check(&VAR) {
+            return self.statement();
+        }
+
+        let mut children: Vec<SyntaxElement> = self.bump().into_iter().collect();
+
+        self.expect(&IDENTIFIER, &mut children);
+
+        if self.check(&EQUAL) {
+            children.extend(self.bump());
+            children.push(SyntaxElement::Node(self.assignment()));
+        }
+
+        self.expect(&SEMICOLON, &mut children);
+
+        SyntaxNode::new(NodeKind::VarDecl, children)
+    }
+
+    fn statement(&mut self) -> SyntaxNode<'a> {
+        let mut children = Vec::new();
+
+        let kind = if self.check(&PRINT) {
+            children.extend(self.bump());
+            NodeKind::PrintStmt
+        } else {
+            NodeKind::ExprStmt
+        };
+
+        children.push(SyntaxElement::Node(self.expression()));
+        self.expect(&SEMICOLON, &mut children);
+
+        SyntaxNode::new(kind, children)
+    }
+
+    fn expression(&mut self) -> SyntaxNode<'a> {
+        let depth = self.depth;
+        let mut left = self.assignment();
+
+        while self.check(&COMMA) {
+            let mut children = vec![SyntaxElement::Node(left)];
+            children.extend(self.bump());
+            children.push(SyntaxElement::Node(self.nested(CstParser::assignment)));
+
+            left = SyntaxNode::new(NodeKind::Comma, children);
+        }
+
+        self.depth = depth;
+        left
+    }
+
+    fn assignment(&mut self) -> SyntaxNode<'a> {
+        let target = self.conditional();
+
+        if !self.check(&EQUAL) {
+            return target;
+        }
+
+        let depth = self.depth;
+        let mut children = vec![SyntaxElement::Node(target)];
+        children.extend(self.bump());
+        children.push(SyntaxElement::Node(self.nested(CstParser::assignment)));
+        self.depth = depth;
+
+        SyntaxNode::new(NodeKind::Assign, children)
+    }
+
+    fn conditional(&mut self) -> SyntaxNode<'a> {
+        let condition = self.binary(1);
+
+        if !self.check(&QUESTION) {
+            return condition;
+        }
+
+        let depth = self.depth;
+        let mut children = vec![SyntaxElement::Node(condition)];
+        children.extend(self.bump());
+        children.push(SyntaxElement::Node(self.nested(CstParser::expression)));
+        self.expect(&COLON, &mut children);
+        children.push(SyntaxElement::Node(self.assignment()));
+        self.depth = depth;
+
+        SyntaxNode::new(NodeKind::Conditional, children)
+    }
+
+    /// Parses binary operators that bind at least as tightly as `min_precedence`.
+    fn binary(&mut self, min_precedence: u8) -> SyntaxNode<'a> {
+        let depth = self.depth;
+        let mut left = self.unary();
+
+        loop {
+            let (precedence, right_associative) = match self.peek() {
+                Some(t) => (t.precedence(), t.is_right_associative()),
+                None => (0, false),
+            };
+
+            if precedence == 0 || precedence < min_precedence {
+                break;
+            }
+
+            let mut children = vec![SyntaxElement::Node(left)];
+            children.extend(self.bump());
+            let next = if right_associative { precedence } else { precedence + 1 };
+            // Each operator in a chain nests what came before it one level deeper
+            children.push(SyntaxElement::Node(self.nested(|p| p.binary(next))));
+
+            left = SyntaxNode::new(NodeKind::Binary, children);
+        }
+
+        self.depth = depth;
+        left
+    }
+
+    fn unary(&mut self) -> SyntaxNode<'a> {
+        if self.check(&BANG) || self.check(&MINUS) || self.check(&TILDE) {
+            let depth = self.depth;
+            let mut children: Vec<SyntaxElement> = self.bump().into_iter().collect();
+            // Only `**` binds tighter than a unary operator
+            children.push(SyntaxElement::Node(self.nested(|p| p.binary(STAR_STAR.precedence()))));
+            self.depth = depth;
+
+            return SyntaxNode::new(NodeKind::Unary, children);
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> SyntaxNode<'a> {
+        let kind = match self.peek() {
+            Some(FALSE) | Some(TRUE) | Some(NIL) | Some(NUMBER) | Some(STRING) | Some(INTERPOLATION(_)) => NodeKind::Literal,
+            Some(IDENTIFIER) => NodeKind::Variable,
+            Some(LEFT_PAREN) => NodeKind::Grouping,
+            // Leave EOF for `program` to pick up
+            Some(EOF) | None => return SyntaxNode::new(NodeKind::Error, Vec::new()),
+            Some(_) => NodeKind::Error,
+        };
+
+        let mut children: Vec<SyntaxElement> = self.bump().into_iter().collect();
+
+        if kind == NodeKind::Grouping {
+            let depth = self.depth;
+            children.push(SyntaxElement::Node(self.nested(CstParser::expression)));
+            self.depth = depth;
+            self.expect(&RIGHT_PAREN, &mut children);
+        }
+
+        SyntaxNode::new(kind, children)
+    }
+
+    /// Parses with `parse` one level deeper, leaving it to the caller to come
+    /// back up. Past `MAX_NESTING`, the rest of the statement goes into an
+    /// `Error` node instead, much as `Parser` reports it and skips ahead.
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> SyntaxNode<'a>) -> SyntaxNode<'a> {
+        if self.depth == MAX_NESTING {
+            let mut children = Vec::new();
+
+            while !self.check(&SEMICOLON) && !self.check(&EOF) && self.peek().is_some() {
+                children.extend(self.bump());
+            }
+
+            return SyntaxNode::new(NodeKind::Error, children);
+        }
+
+        self.depth += 1;
+        parse(self)
+    }
+
+    /// Takes the next token if it's the one we expect. If not, it is left for
+    /// whatever comes next and an empty `Error` node marks the gap.
+    fn expect(&mut self, expected: &TokenType, children: &mut Vec<SyntaxElement<'a>>) {
+        if self.check(expected) {
+            children.extend(self.bump());
+        } else {
+            children.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::Error, Vec::new())));
+        }
+    }
+
+    fn check(&self, expected: &TokenType) -> bool {
+        self.peek() == Some(expected)
+    }
+
+    fn peek(&self) -> Option<&TokenType<'a>> {
+        self.tokens.last().map(|t| &t.token.token_type)
+    }
+
+    fn bump(&mut self) -> Option<SyntaxElement<'a>> {
+        self.tokens.pop().map(SyntaxElement::Token)
+    }
+}
+
+// Trait implementations
+
+// DISPLAY TRAIT
+// Displaying any part of the tree gives back its exact source text.
+impl fmt::Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl fmt::Display for SyntaxToken<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for t in &self.leading {
+            t.fmt(f)?;
+        }
+
+        write!(f, "{}", self.token.lexeme())?;
+
+        for t in &self.trailing {
+            t.fmt(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SyntaxElement<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxElement::Node(n) => n.fmt(f),
+            SyntaxElement::Token(t) => t.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            child.fmt(f)?;
+        }
+
+        Ok(())
+    }
+}
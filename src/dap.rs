@@ -0,0 +1,459 @@
+//! # Lox Debug Adapter
+//!
+//! A Debug Adapter Protocol server on stdin and stdout, so editors can debug
+//! Lox programs: set breakpoints, step, and look at stack frames, scopes and
+//! variables while the program is paused.
+//!
+//! Everything happens on one thread. Before the program starts, the adapter
+//! answers requests in `run`. Once the client sends `configurationDone` the
+//! program runs with the adapter attached as an interpreter `Hook`, and while
+//! the program is paused the hook answers requests itself until one of them
+//! resumes it. What the program prints is sent to the client as `output`
+//! events, since stdout carries the protocol.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use serde_json::{json, Value};
+use crate::debugger::{Mode, STOPPED};
+use crate::expr::Expr;
+use crate::interpreter::{Hook, Interpreter, RuntimeError, RuntimeErrorKind};
+use crate::lox::{EX_IOERR, EX_SOFTWARE};
+use crate::loxerror::LoxError;
+use crate::lsp::{read_message, write_message};
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+
+// Lox programs are single-threaded
+const THREAD_ID: i64 = 1;
+
+// The only scope a frame has until Lox grows functions
+const GLOBALS_REFERENCE: i64 = 1;
+
+/// Serves a debugging session on stdin and stdout, returning the exit code.
+pub fn run() -> i32 {
+    let adapter = Rc::new(RefCell::new(Adapter::new(
+        Box::new(io::BufReader::new(io::stdin())),
+        Box::new(io::stdout()),
+    )));
+
+    match serve(&adapter) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("Debug adapter failed: {}", e);
+            EX_IOERR
+        }
+    }
+}
+
+/// Handles requests until the client disconnects or closes the input.
+pub fn serve(adapter: &Rc<RefCell<Adapter>>) -> io::Result<()> {
+    loop {
+        let request = match adapter.borrow_mut().read()? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+
+        let command = request["command"].as_str().unwrap_or("");
+
+        match command {
+            "configurationDone" => {
+                adapter.borrow_mut().respond(&request, Ok(Value::Null))?;
+                launch(adapter)?;
+            },
+            "disconnect" | "terminate" => {
+                adapter.borrow_mut().respond(&request, Ok(Value::Null))?;
+                return Ok(());
+            },
+            _ => {
+                let result = adapter.borrow_mut().handle(&request, None);
+                adapter.borrow_mut().respond(&request, result)?;
+
+                if command == "initialize" {
+                    adapter.borrow_mut().event("initialized", Value::Null)?;
+                }
+            }
+        }
+    }
+}
+
+/// Runs the launched program to completion with the adapter attached.
+fn launch(adapter: &Rc<RefCell<Adapter>>) -> io::Result<()> {
+    let source = match adapter.borrow_mut().source.take() {
+        Some(s) => s,
+        // The client never sent a (successful) `launch`
+        None => return Ok(()),
+    };
+
+    // The statements borrow their tokens from the source, so they're parsed
+    // here rather than kept on the adapter. `launch` already checked they parse.
+    let statements = match parse(&source) {
+        Ok(s) => s,
+        Err(_) => return Ok(()),
+    };
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(Box::new(AdapterHook(Rc::clone(adapter))));
+    interpreter.set_output(Box::new(AdapterOutput(Rc::clone(adapter), Vec::new())));
+
+    let result = interpreter.interpret(&statements);
+
+    // Let go of the adapter before using it again
+    drop(interpreter);
+
+    let mut adapter = adapter.borrow_mut();
+
+    let exit_code = match result {
+        Ok(_) => 0,
+        Err(e) if e.kind() == RuntimeErrorKind::Stopped => 0,
+        Err(e) => {
+            let message = format!("{}\n", LoxError::from(e));
+            adapter.event("output", json!({ "category": "stderr", "output": message }))?;
+            EX_SOFTWARE
+        }
+    };
+
+    adapter.event("exited", json!({ "exitCode": exit_code }))?;
+    adapter.event("terminated", Value::Null)
+}
+
+/// Parses a program, describing the first thing wrong with it if it doesn't.
+fn parse(source: &str) -> Result<Vec<Stmt<'_>>, String> {
+    let (tokens, scan_errors) = Scanner::new(source).scan_tokens_quietly();
+
+    if let Some(e) = scan_errors.first() {
+        return Err(format!("[line {}] Error: {}", e.line, e.message));
+    }
+
+    Parser::new(tokens).parse().map_err(|e| LoxError::from(e).to_string())
+}
+
+pub struct Adapter {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    // Sequence number of the next message we send
+    seq: i64,
+    path: String,
+    lines: Vec<String>,
+    // The source of the launched program
+    source: Option<String>,
+    args: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    mode: Mode,
+}
+
+impl Adapter {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Adapter {
+            input,
+            output,
+            seq: 1,
+            path: String::new(),
+            lines: Vec::new(),
+            source: None,
+            args: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: false,
+            mode: Mode::Continue,
+        }
+    }
+
+    /// The arguments the program was launched with. Lox has no way for a
+    /// program to read them yet, so until it does they're only kept here.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        loop {
+            match read_message(&mut self.input)? {
+                Some(Ok(message)) => return Ok(Some(message)),
+                // A message we can't read has no seq to respond to, so tell the user instead
+                Some(Err(e)) => self.event("output", json!({ "category": "console", "output": format!("{}\n", e) }))?,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+
+        match result {
+            Ok(Value::Null) => {},
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+
+        if !body.is_null() {
+            message["body"] = body;
+        }
+
+        self.send(message)
+    }
+
+    /// Answers requests that don't start, resume or stop the program.
+    /// Those about the running program need its `interpreter`.
+    fn handle(&mut self, request: &Value, interpreter: Option<&mut Interpreter>) -> Result<Value, String> {
+        let arguments = &request["arguments"];
+
+        match (request["command"].as_str().unwrap_or(""), interpreter) {
+            ("initialize", _) => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            ("launch", _) => self.launch(arguments),
+            ("setBreakpoints", _) => Ok(self.set_breakpoints(arguments)),
+            ("setExceptionBreakpoints", _) => Ok(json!({ "breakpoints": [] })),
+            ("threads", _) => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            ("stackTrace", Some(interpreter)) => Ok(self.stack_trace(interpreter)),
+            ("scopes", Some(_)) => Ok(json!({
+                "scopes": [{ "name": "Globals", "variablesReference": GLOBALS_REFERENCE, "expensive": false }],
+            })),
+            ("variables", Some(interpreter)) => Ok(variables(interpreter, arguments)),
+            ("evaluate", Some(interpreter)) => evaluate(interpreter, arguments),
+            (command @ "stackTrace", None) | (command @ "scopes", None) |
+            (command @ "variables", None) | (command @ "evaluate", None) => Err(format!("Can't {} while the program isn't paused", command)),
+            (command, _) => Err(format!("Unsupported request {}", command)),
+        }
+    }
+
+    /// Loads the program; it starts running once configuration is done.
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["program"].as_str().ok_or("Missing program to launch")?;
+
+        let args = match &arguments["args"] {
+            Value::Null => Vec::new(),
+            Value::Array(args) => args.iter()
+                .map(|a| a.as_str().map(String::from).ok_or("Program arguments must be strings"))
+                .collect::<Result<_, _>>()?,
+            _ => return Err(String::from("Program arguments must be a list")),
+        };
+
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read file {}: {}", path, e))?;
+
+        parse(&source)?;
+
+        self.path = String::from(path);
+        self.lines = source.lines().map(String::from).collect();
+        self.source = Some(source);
+        self.args = args;
+
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"].as_str().unwrap_or("");
+        let ours = Path::new(path) == Path::new(&self.path);
+
+        if ours {
+            self.breakpoints.clear();
+        }
+
+        let breakpoints: Vec<Value> = arguments["breakpoints"].as_array()
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .filter_map(|b| b["line"].as_u64())
+            .map(|line| {
+                let line = line as usize;
+                let verified = ours && line >= 1 && line <= self.lines.len();
+
+                if verified {
+                    self.breakpoints.insert(line);
+                }
+
+                json!({ "verified": verified, "line": line })
+            })
+            .collect();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&self, interpreter: &Interpreter) -> Value {
+        let name = Path::new(&self.path).file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+
+        let frames: Vec<Value> = interpreter.frames.iter()
+            .enumerate()
+            .rev()
+            .map(|(id, frame)| json!({
+                "id": id,
+                "name": frame.name,
+                "source": { "name": name, "path": self.path },
+                "line": frame.line,
+                "column": 1,
+            }))
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    /// Tells the client why we stopped, then answers its requests until one resumes the program.
+    fn pause(&mut self, interpreter: &mut Interpreter, reason: &str) -> Result<(), RuntimeError> {
+        let line = interpreter.frames.last().map_or(0, |f| f.line);
+        let depth = interpreter.frames.len();
+
+        // Losing the client means there's nobody left to debug for
+        let stopped = |_| RuntimeError::stopped(line, STOPPED);
+
+        self.event("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }))
+            .map_err(stopped)?;
+
+        loop {
+            let request = match self.read().map_err(stopped)? {
+                Some(r) => r,
+                None => return Err(RuntimeError::stopped(line, STOPPED)),
+            };
+
+            let mode = match request["command"].as_str().unwrap_or("") {
+                "continue" => Some(Mode::Continue),
+                "next" => Some(Mode::StepOver(depth)),
+                "stepIn" => Some(Mode::StepIn),
+                "stepOut" => Some(Mode::StepOut(depth)),
+                "disconnect" | "terminate" => {
+                    self.respond(&request, Ok(Value::Null)).map_err(stopped)?;
+                    return Err(RuntimeError::stopped(line, STOPPED));
+                },
+                _ => None,
+            };
+
+            match mode {
+                Some(mode) => {
+                    self.mode = mode;
+
+                    let body = if request["command"] == "continue" { json!({ "allThreadsContinued": true }) } else { Value::Null };
+                    self.respond(&request, Ok(body)).map_err(stopped)?;
+
+                    return Ok(());
+                },
+                None => {
+                    let result = self.handle(&request, Some(interpreter));
+                    self.respond(&request, result).map_err(stopped)?;
+                }
+            }
+        }
+    }
+}
+
+fn variables(interpreter: &Interpreter, arguments: &Value) -> Value {
+    if arguments["variablesReference"].as_i64() != Some(GLOBALS_REFERENCE) {
+        return json!({ "variables": [] });
+    }
+
+    let variables: Vec<Value> = interpreter.environment.values()
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value.to_string(), "variablesReference": 0 }))
+        .collect();
+
+    json!({ "variables": variables })
+}
+
+/// Evaluates an expression against the paused program's variables. Until Lox
+/// has functions every frame sees the same globals, so any frame the client
+/// names is evaluated in the same environment, as long as it exists.
+fn evaluate(interpreter: &mut Interpreter, arguments: &Value) -> Result<Value, String> {
+    let source = arguments["expression"].as_str().ok_or("Missing expression")?;
+
+    if let Some(id) = arguments.get("frameId") {
+        match id.as_u64() {
+            Some(id) if (id as usize) < interpreter.frames.len() => {},
+            _ => return Err(format!("Unknown frame {}", id)),
+        }
+    }
+
+    let (tokens, scan_errors) = Scanner::new(source).scan_tokens_quietly();
+
+    if let Some(e) = scan_errors.first() {
+        return Err(e.message.clone());
+    }
+
+    let expression = Parser::new(tokens).parse_expression().map_err(|e| LoxError::from(e).to_string())?;
+    let value = interpreter.evaluate(&expression).map_err(|e| e.message().to_string())?;
+
+    Ok(json!({ "result": value.to_string(), "variablesReference": 0 }))
+}
+
+struct AdapterHook(Rc<RefCell<Adapter>>);
+
+impl Hook for AdapterHook {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, statement: &Stmt) -> Result<(), RuntimeError> {
+        let mut adapter = self.0.borrow_mut();
+
+        if adapter.stop_on_entry {
+            adapter.stop_on_entry = false;
+            adapter.pause(interpreter, "entry")
+        } else if adapter.breakpoints.contains(&statement.line()) {
+            adapter.pause(interpreter, "breakpoint")
+        } else if adapter.mode.stops_at(interpreter.frames.len()) {
+            adapter.pause(interpreter, "step")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn before_expression(&mut self, interpreter: &mut Interpreter, _expression: &Expr) -> Result<(), RuntimeError> {
+        let mut adapter = self.0.borrow_mut();
+
+        match adapter.mode {
+            Mode::StepIn => adapter.pause(interpreter, "step"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Passes what the program prints on to the client, a line at a time.
+struct AdapterOutput(Rc<RefCell<Adapter>>, Vec<u8>);
+
+impl AdapterOutput {
+    fn send(&mut self, bytes: Vec<u8>) -> io::Result<()> {
+        let output = String::from_utf8_lossy(&bytes);
+
+        self.0.borrow_mut().event("output", json!({ "category": "stdout", "output": output }))
+    }
+}
+
+impl Write for AdapterOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.extend_from_slice(buf);
+
+        if let Some(end) = self.1.iter().rposition(|b| *b == b'\n') {
+            let lines = self.1.drain(..=end).collect();
+            self.send(lines)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.1.is_empty() {
+            return Ok(());
+        }
+
+        let rest = std::mem::take(&mut self.1);
+        self.send(rest)
+    }
+}
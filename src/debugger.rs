@@ -0,0 +1,274 @@
+//! # Lox Debugger
+//! 
+//! An interactive, gdb-style debugger for `rlox debug file.lox`. It attaches to
+//! the interpreter as a `Hook` and pauses the program at breakpoints and after
+//! steps, reading commands from stdin while it is paused.
+//!
+//! A user who quits stops the program with a `Stopped` runtime error, which
+//! isn't reported as the program's fault.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use crate::expr::Expr;
+use crate::formatter;
+use crate::interpreter::{Hook, Interpreter, RuntimeError, RuntimeErrorKind};
+use crate::lox::{Lox, EX_DATAERR, EX_NOINPUT, EX_SOFTWARE};
+use crate::loxerror::LoxError;
+use crate::stmt::Stmt;
+
+const HELP: &str = "\
+step, s               Run until the next statement or expression
+next, n               Run until the next statement in this frame
+out, o                Run until the current frame returns
+continue, c           Run until a breakpoint or the end of the program
+break, b [file:]line  Set a breakpoint
+delete, d [file:]line Remove a breakpoint
+breakpoints           List breakpoints
+backtrace, bt         Show the call stack
+locals                Show the variables local to the current frame
+globals               Show the global variables
+print, p <name>       Show the value of a variable
+list, l               Show the source around the current line
+quit, q               Stop the program
+help, h               Show this message
+
+An empty line repeats the previous command.";
+
+/// What the program stops with when the user ends the debugging session.
+pub const STOPPED: &str = "Program stopped by the debugger";
+
+/// Debugs the script at `path` and returns the exit code the process should use.
+pub fn run(path: &str) -> i32 {
+    run_with(path, Box::new(io::BufReader::new(io::stdin())), Box::new(io::stdout()))
+}
+
+/// Like `run`, but reads the user's commands from `input` and answers them on `output`.
+pub fn run_with(path: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Could not read file {}: {}", path, e);
+            return EX_NOINPUT;
+        }
+    };
+
+    let statements = match Lox::parse(&source) {
+        Some(statements) => statements,
+        None => return EX_DATAERR,
+    };
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(Box::new(Debugger::new(path, &source, input, output)));
+
+    println!("Debugging {}. Type `help` for a list of commands.", path);
+
+    match interpreter.interpret(&statements) {
+        Ok(_) => {
+            println!("Program finished.");
+            0
+        },
+        Err(e) if e.kind() == RuntimeErrorKind::Stopped => 0,
+        Err(e) => {
+            eprintln!("{}", LoxError::from(e));
+            EX_SOFTWARE
+        }
+    }
+}
+
+/// How far the program runs before a debugger pauses it again.
+pub enum Mode {
+    Continue,
+    StepIn,
+    // Stop at the next statement in a frame at most this deep
+    StepOver(usize),
+    // Stop at the next statement in a frame shallower than this
+    StepOut(usize),
+}
+
+impl Mode {
+    /// Whether to pause before a statement running `depth` frames deep.
+    pub fn stops_at(&self, depth: usize) -> bool {
+        match self {
+            Mode::Continue => false,
+            Mode::StepIn => true,
+            Mode::StepOver(d) => depth <= *d,
+            Mode::StepOut(d) => depth < *d,
+        }
+    }
+}
+
+pub struct Debugger {
+    file: String,
+    lines: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    last_command: String,
+}
+
+impl Debugger {
+    /// Debugs `file`, reading commands from `input` and answering them on `output`.
+    pub fn new(file: &str, source: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Debugger {
+            file: String::from(file),
+            lines: source.lines().map(String::from).collect(),
+            breakpoints: BTreeSet::new(),
+            // Stop before the first statement so breakpoints can be set
+            mode: Mode::StepOver(1),
+            input,
+            output,
+            last_command: String::new(),
+        }
+    }
+
+    /// Shows where we are and handles commands until one of them resumes the program.
+    fn pause(&mut self, interpreter: &mut Interpreter, line: usize, expression: Option<&Expr>) -> Result<(), RuntimeError> {
+        match expression {
+            Some(e) => self.say(&format!("{}:{}: evaluating {}", self.file, line, formatter::format_expr(e))),
+            None => self.say(&format!("{}:{}: {}", self.file, line, self.source_line(line))),
+        }
+
+        let depth = interpreter.frames.len();
+
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+
+            let mut command = String::new();
+
+            match self.input.read_line(&mut command) {
+                // Leaving the debugger's input is like quitting
+                Ok(0) | Err(_) => return Err(RuntimeError::stopped(line, STOPPED)),
+                Ok(_) => {},
+            }
+
+            let mut command = command.trim().to_string();
+
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+
+            self.last_command = command.clone();
+
+            let (name, arg) = match command.split_once(char::is_whitespace) {
+                Some((n, a)) => (n, a.trim()),
+                None => (command.as_str(), ""),
+            };
+
+            match name {
+                "s" | "step" => { self.mode = Mode::StepIn; return Ok(()); },
+                "n" | "next" => { self.mode = Mode::StepOver(depth); return Ok(()); },
+                "o" | "out" => { self.mode = Mode::StepOut(depth); return Ok(()); },
+                "c" | "continue" => { self.mode = Mode::Continue; return Ok(()); },
+                "q" | "quit" => return Err(RuntimeError::stopped(line, STOPPED)),
+                "b" | "break" => match self.parse_location(arg) {
+                    Ok(l) => {
+                        self.breakpoints.insert(l);
+                        self.say(&format!("Breakpoint set at {}:{}", self.file, l));
+                    },
+                    Err(msg) => self.say(&msg),
+                },
+                "d" | "delete" => match self.parse_location(arg) {
+                    Ok(l) if self.breakpoints.remove(&l) => self.say(&format!("Breakpoint at {}:{} deleted", self.file, l)),
+                    Ok(l) => self.say(&format!("No breakpoint at {}:{}", self.file, l)),
+                    Err(msg) => self.say(&msg),
+                },
+                "breakpoints" => {
+                    if self.breakpoints.is_empty() {
+                        self.say("No breakpoints");
+                    }
+
+                    let listing: Vec<String> = self.breakpoints.iter()
+                        .map(|l| format!("{}:{}: {}", self.file, l, self.source_line(*l)))
+                        .collect();
+
+                    for l in listing {
+                        self.say(&l);
+                    }
+                },
+                "bt" | "backtrace" => {
+                    for (i, frame) in interpreter.frames.iter().rev().enumerate() {
+                        self.say(&format!("#{} {} at {}:{}", i, frame.name, self.file, frame.line));
+                    }
+                },
+                // Until Lox has functions, the script's frame only has globals
+                "locals" => self.say("No local variables in this frame; see `globals`"),
+                "globals" => {
+                    for (name, value) in interpreter.environment.values() {
+                        self.say(&format!("{} = {}", name, value));
+                    }
+                },
+                "p" | "print" => match interpreter.environment.values().into_iter().find(|(n, _)| *n == arg) {
+                    Some((name, value)) => self.say(&format!("{} = {}", name, value)),
+                    None => self.say(&format!("No variable named '{}'", arg)),
+                },
+                "l" | "list" => {
+                    let first = line.saturating_sub(3).max(1);
+
+                    for l in first..(line + 4).min(self.lines.len() + 1) {
+                        let marker = if l == line { "->" } else { "  " };
+                        self.say(&format!("{} {:>4} {}", marker, l, self.source_line(l)));
+                    }
+                },
+                "h" | "help" => self.say(HELP),
+                _ => self.say(&format!("Unknown command '{}'. Type `help` for a list of commands.", name)),
+            }
+        }
+    }
+
+    /// Writes a line of the debugger's own output. Losing it is no reason to stop the program.
+    fn say(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+
+    /// Parses `line` or `file:line`, checking that the line is in our file.
+    fn parse_location(&self, location: &str) -> Result<usize, String> {
+        let (file, line) = match location.rsplit_once(':') {
+            Some((f, l)) => (Some(f), l),
+            None => (None, location),
+        };
+
+        if let Some(file) = file {
+            if Path::new(file).file_name() != Path::new(&self.file).file_name() {
+                return Err(format!("Only {} is being debugged", self.file));
+            }
+        }
+
+        match line.parse::<usize>() {
+            Ok(l) if l >= 1 && l <= self.lines.len() => Ok(l),
+            _ => Err(format!("Expected a line between 1 and {}, not '{}'", self.lines.len(), line)),
+        }
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        self.lines.get(line.wrapping_sub(1)).map_or("", |l| l.trim())
+    }
+}
+
+impl Hook for Debugger {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, statement: &Stmt) -> Result<(), RuntimeError> {
+        let line = statement.line();
+
+        if self.breakpoints.contains(&line) || self.mode.stops_at(interpreter.frames.len()) {
+            self.pause(interpreter, line, None)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn before_expression(&mut self, interpreter: &mut Interpreter, expression: &Expr) -> Result<(), RuntimeError> {
+        match self.mode {
+            Mode::StepIn => {
+                let line = expression.line()
+                    .or_else(|| interpreter.frames.last().map(|f| f.line))
+                    .unwrap_or(0);
+
+                self.pause(interpreter, line, Some(expression))
+            },
+            _ => Ok(()),
+        }
+    }
+}
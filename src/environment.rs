@@ -0,0 +1,45 @@
+//! # Lox Environment
+//! 
+//! Storage for the variables defined while a program runs.
+
+use std::collections::HashMap;
+use crate::loxvalue::LoxValue;
+use crate::token::Token;
+use crate::interpreter::RuntimeError;
+
+#[derive(Default)]
+pub struct Environment {
+    values: HashMap<String, LoxValue>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self { values: HashMap::new() }
+    }
+
+    /// Binds `name` to `value`. Redefining an existing variable is allowed.
+    pub fn define(&mut self, name: &str, value: LoxValue) {
+        self.values.insert(String::from(name), value);
+    }
+
+    /// All defined variables, sorted by name.
+    pub fn values(&self) -> Vec<(&String, &LoxValue)> {
+        let mut values: Vec<_> = self.values.iter().collect();
+        values.sort_by(|a, b| a.0.cmp(b.0));
+        values
+    }
+
+    pub fn get(&self, name: &Token) -> Result<LoxValue, RuntimeError> {
+        match self.values.get(name.lexeme()) {
+            Some(v) => Ok(v.clone()),
+            None => Err(RuntimeError::new(name.clone(), &format!("Undefined variable '{}'", name.lexeme()))),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: LoxValue) -> Result<(), RuntimeError> {
+        match self.values.get_mut(name.lexeme()) {
+            Some(v) => { *v = value; Ok(()) },
+            None => Err(RuntimeError::new(name.clone(), &format!("Undefined variable '{}'", name.lexeme()))),
+        }
+    }
+}
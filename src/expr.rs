@@ -4,82 +4,186 @@
 use std::fmt;
 use crate::token;
 
-pub struct Ast(Box<Expr>);
+#[allow(dead_code)]
+pub struct Ast<'a>(Box<Expr<'a>>);
 
-pub enum Expr {
-    Binary(BinaryExpr),
-    Unary(UnaryExpr),
+pub enum Expr<'a> {
+    Binary(BinaryExpr<'a>),
+    Unary(UnaryExpr<'a>),
     Literal(LiteralExpr),
-    Grouping(GroupingExpr),
+    Grouping(GroupingExpr<'a>),
+    Variable(VariableExpr<'a>),
+    Assign(AssignExpr<'a>),
+    Interpolation(InterpolationExpr<'a>),
+    Conditional(ConditionalExpr<'a>),
+    Comma(CommaExpr<'a>),
 }
 
-pub struct BinaryExpr {
-    pub left: Box<Expr>,
-    pub operator: token::Token,
-    pub right: Box<Expr>,
+impl Expr<'_> {
+    /// The line the expression starts on, if it kept a token we can tell from.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::Binary(b) => b.left.line().or(Some(b.operator.line)),
+            Expr::Unary(u) => Some(u.operator.line),
+            Expr::Literal(_) => None,
+            Expr::Grouping(g) => g.0.line(),
+            Expr::Variable(v) => Some(v.name.line),
+            Expr::Assign(a) => Some(a.name.line),
+            Expr::Interpolation(i) => Some(i.line),
+            Expr::Conditional(c) => c.condition.line().or(Some(c.question.line)),
+            Expr::Comma(c) => c.left.line(),
+        }
+    }
+}
+
+pub struct BinaryExpr<'a> {
+    pub left: Box<Expr<'a>>,
+    pub operator: token::Token<'a>,
+    pub right: Box<Expr<'a>>,
 }
 
-impl BinaryExpr {
-    pub fn new(left: Expr, operator: token::Token, right: Expr) -> Self {
+impl<'a> BinaryExpr<'a> {
+    pub fn new(left: Expr<'a>, operator: token::Token<'a>, right: Expr<'a>) -> Self {
         BinaryExpr {
             left: Box::new(left),
-            operator: operator,
+            operator,
             right: Box::new(right),
         }
     }
 }
 
-pub struct UnaryExpr {
-    pub operator: token::Token,
-    pub operand: Box<Expr>,
+pub struct UnaryExpr<'a> {
+    pub operator: token::Token<'a>,
+    pub operand: Box<Expr<'a>>,
 }
 
-impl UnaryExpr {
-    pub fn new(operator: token::Token, operand: Expr) -> Self {
+impl<'a> UnaryExpr<'a> {
+    pub fn new(operator: token::Token<'a>, operand: Expr<'a>) -> Self {
         UnaryExpr {
-            operator: operator,
+            operator,
             operand: Box::new(operand),
         }
     }
 }
 
 pub enum LiteralExpr {
-    Number(f64),
+    // The value, and the literal as it was written, like `0xFF` or `1_000`
+    Number(f64, String),
     String(String),
     Bool(bool),
     Nil
 }
 
-pub struct GroupingExpr(pub Box<Expr>);
+pub struct GroupingExpr<'a>(pub Box<Expr<'a>>);
 
-impl GroupingExpr {
-    pub fn new(inner: Expr) -> Self {
+impl<'a> GroupingExpr<'a> {
+    pub fn new(inner: Expr<'a>) -> Self {
         GroupingExpr(Box::new(inner))
     }
 }
 
+pub struct VariableExpr<'a> {
+    pub name: token::Token<'a>,
+}
+
+impl<'a> VariableExpr<'a> {
+    pub fn new(name: token::Token<'a>) -> Self {
+        VariableExpr { name }
+    }
+}
+
+pub struct AssignExpr<'a> {
+    pub name: token::Token<'a>,
+    pub value: Box<Expr<'a>>,
+}
+
+impl<'a> AssignExpr<'a> {
+    pub fn new(name: token::Token<'a>, value: Expr<'a>) -> Self {
+        AssignExpr {
+            name,
+            value: Box::new(value),
+        }
+    }
+}
+
+/// A string with expressions spliced into it, like `"Hello ${name}"`.
+pub struct InterpolationExpr<'a> {
+    pub parts: Vec<InterpolationPart<'a>>,
+    pub line: usize,
+}
+
+pub enum InterpolationPart<'a> {
+    Text(String),
+    Expr(Expr<'a>),
+}
+
+impl<'a> InterpolationExpr<'a> {
+    pub fn new(parts: Vec<InterpolationPart<'a>>, line: usize) -> Self {
+        InterpolationExpr { parts, line }
+    }
+}
+
+/// `condition ? then_branch : else_branch`, which only evaluates the branch it picks.
+pub struct ConditionalExpr<'a> {
+    pub condition: Box<Expr<'a>>,
+    pub question: token::Token<'a>,
+    pub then_branch: Box<Expr<'a>>,
+    pub else_branch: Box<Expr<'a>>,
+}
+
+impl<'a> ConditionalExpr<'a> {
+    pub fn new(condition: Expr<'a>, question: token::Token<'a>, then_branch: Expr<'a>, else_branch: Expr<'a>) -> Self {
+        ConditionalExpr {
+            condition: Box::new(condition),
+            question,
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        }
+    }
+}
+
+/// `left, right`, which evaluates both and is worth what `right` is.
+pub struct CommaExpr<'a> {
+    pub left: Box<Expr<'a>>,
+    pub right: Box<Expr<'a>>,
+}
+
+impl<'a> CommaExpr<'a> {
+    pub fn new(left: Expr<'a>, right: Expr<'a>) -> Self {
+        CommaExpr {
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+}
+
 // Trait implementations
 
 // DISPLAY TRAIT
-impl fmt::Display for Expr {
+impl fmt::Display for Expr<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expr::Binary(b) => b.fmt(f),
             Expr::Unary(u) => u.fmt(f),
             Expr::Literal(l) => l.fmt(f),
             Expr::Grouping(g) => g.fmt(f),
+            Expr::Variable(v) => v.fmt(f),
+            Expr::Assign(a) => a.fmt(f),
+            Expr::Interpolation(i) => i.fmt(f),
+            Expr::Conditional(c) => c.fmt(f),
+            Expr::Comma(c) => c.fmt(f),
         }
     }
 }
-impl fmt::Display for BinaryExpr {
+impl fmt::Display for BinaryExpr<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({} {} {})", self.operator.lexeme, self.left, self.right)
+        write!(f, "({} {} {})", self.operator.lexeme(), self.left, self.right)
     }
 }
 
-impl fmt::Display for UnaryExpr {
+impl fmt::Display for UnaryExpr<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({} {})", self.operand, self.operator.lexeme)
+        write!(f, "({} {})", self.operand, self.operator.lexeme())
     }
 }
 
@@ -87,15 +191,54 @@ impl fmt::Display for LiteralExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             LiteralExpr::Bool(b) => write!(f, "{}", b),
-            LiteralExpr::Number(n) => write!(f, "{}", n),
+            LiteralExpr::Number(n, _) => write!(f, "{}", n),
             LiteralExpr::String(s) => write!(f, "'{}'", s),
             LiteralExpr::Nil => write!(f, "nil"),
         }
     }
 }
 
-impl fmt::Display for GroupingExpr {
+impl fmt::Display for GroupingExpr<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "(group {})", self.0)
     }
 }
+
+impl fmt::Display for VariableExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name.lexeme())
+    }
+}
+
+impl fmt::Display for AssignExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(= {} {})", self.name.lexeme(), self.value)
+    }
+}
+
+impl fmt::Display for InterpolationExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(interpolate")?;
+
+        for part in &self.parts {
+            match part {
+                InterpolationPart::Text(t) => write!(f, " '{}'", t)?,
+                InterpolationPart::Expr(e) => write!(f, " {}", e)?,
+            }
+        }
+
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for ConditionalExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(?: {} {} {})", self.condition, self.then_branch, self.else_branch)
+    }
+}
+
+impl fmt::Display for CommaExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(, {} {})", self.left, self.right)
+    }
+}
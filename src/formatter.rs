@@ -0,0 +1,207 @@
+//! # Lox Formatter
+//! 
+//! Turns a parsed program back into Lox source in a canonical style: one
+//! statement per line, single spaces around binary operators, at most one
+//! blank line between statements, and lines wrapped before an operator once
+//! they grow past `MAX_WIDTH` columns. Comments are kept, either on a line of
+//! their own or trailing the statement they followed in the original source.
+
+use std::ops::RangeInclusive;
+use crate::expr::{Expr, LiteralExpr, InterpolationPart};
+use crate::stmt::Stmt;
+use crate::token::Comment;
+
+pub const MAX_WIDTH: usize = 80;
+
+const INDENT: &str = "    ";
+
+pub fn format_program(statements: &[(Stmt, RangeInclusive<usize>)], comments: &[Comment]) -> String {
+    let mut out = String::new();
+    let mut comments = comments.iter().peekable();
+    // The last source line we have emitted something for
+    let mut last_line: Option<usize> = None;
+
+    for (statement, lines) in statements {
+        // Comments before the statement, or inside a statement that spans
+        // several lines, go on their own lines above it
+        while let Some(c) = comments.next_if(|c| c.line < *lines.end()) {
+            if c.line < *lines.start() {
+                separate(&mut out, last_line, c.line);
+            }
+
+            out.push_str(&c.text);
+            out.push('\n');
+            last_line = Some(c.end_line());
+        }
+
+        separate(&mut out, last_line, *lines.start());
+        out.push_str(&format_stmt(statement));
+
+        let trailing = comments.next_if(|c| c.line == *lines.end());
+
+        if let Some(c) = trailing {
+            out.push(' ');
+            out.push_str(&c.text);
+        }
+
+        out.push('\n');
+        last_line = Some(trailing.map_or(*lines.end(), |c| c.end_line()));
+    }
+
+    for c in comments {
+        separate(&mut out, last_line, c.line);
+        out.push_str(&c.text);
+        out.push('\n');
+        last_line = Some(c.end_line());
+    }
+
+    out
+}
+
+/// Keeps a single blank line wherever the original had one or more.
+fn separate(out: &mut String, last_line: Option<usize>, line: usize) {
+    if let Some(last) = last_line {
+        if line > last + 1 {
+            out.push('\n');
+        }
+    }
+}
+
+pub fn format_stmt(statement: &Stmt) -> String {
+    match statement {
+        Stmt::Expression(e) => format!("{};", layout(&e.expression, 0, 0)),
+        Stmt::Print(p) => format!("print {};", layout(&p.expression, "print ".len(), 0)),
+        Stmt::Var(v) => match &v.initializer {
+            Some(i) => {
+                let prefix = format!("var {} = ", v.name.lexeme());
+                format!("{}{};", prefix, layout(i, width(&prefix), 0))
+            },
+            None => format!("var {};", v.name.lexeme()),
+        },
+    }
+}
+
+/// Formats `expr` on a single line, however long it gets.
+pub fn format_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Binary(b) => format!("{} {} {}", format_expr(&b.left), b.operator.lexeme(), format_expr(&b.right)),
+        Expr::Unary(u) => format!("{}{}", u.operator.lexeme(), format_expr(&u.operand)),
+        Expr::Literal(LiteralExpr::String(s)) => format!("\"{}\"", escape(s)),
+        // Keep the number the way it was written, so `0xFF` doesn't become `255`
+        Expr::Literal(LiteralExpr::Number(_, lexeme)) => lexeme.clone(),
+        Expr::Literal(l) => l.to_string(),
+        Expr::Grouping(g) => format!("({})", format_expr(&g.0)),
+        Expr::Variable(v) => v.name.lexeme().to_string(),
+        Expr::Assign(a) => format!("{} = {}", a.name.lexeme(), format_expr(&a.value)),
+        Expr::Interpolation(i) => {
+            let parts: Vec<String> = i.parts.iter().map(|p| match p {
+                InterpolationPart::Text(t) => escape(t),
+                InterpolationPart::Expr(e) => format!("${{{}}}", format_expr(e)),
+            }).collect();
+
+            format!("\"{}\"", parts.concat())
+        },
+        Expr::Conditional(c) => format!(
+            "{} ? {} : {}",
+            format_expr(&c.condition), format_expr(&c.then_branch), format_expr(&c.else_branch),
+        ),
+        Expr::Comma(c) => format!("{}, {}", format_expr(&c.left), format_expr(&c.right)),
+    }
+}
+
+/// Writes `s` the way it would appear between quotes in source: the characters
+/// that can't appear there as they are, and other control characters, escaped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            // Otherwise it would start an interpolation
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:X}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Formats `expr`, which starts at `column`, breaking it before binary operators
+/// when it won't fit. Continuation lines are indented one level past `indent`.
+fn layout(expr: &Expr, column: usize, indent: usize) -> String {
+    let flat = format_expr(expr);
+
+    // The `;` that ends the statement needs room too
+    if column + width(&flat) < MAX_WIDTH {
+        return flat;
+    }
+
+    match expr {
+        Expr::Binary(b) => {
+            // Lay a chain like `a + b - c` out as one unit, fitting as many
+            // operands on each line as we can
+            let mut operands = Vec::new();
+            let mut left = expr;
+
+            while let Expr::Binary(l) = left {
+                if l.operator.token_type.precedence() != b.operator.token_type.precedence() {
+                    break;
+                }
+
+                operands.push((l.operator.lexeme(), &*l.right));
+                left = &l.left;
+            }
+
+            operands.reverse();
+
+            let inner = indent + 1;
+            let mut out = layout(left, column, indent);
+            let mut column = end_column(column, &out);
+
+            for (operator, operand) in operands {
+                let piece = format!("{} {}", operator, format_expr(operand));
+
+                if column + 1 + width(&piece) < MAX_WIDTH {
+                    out.push(' ');
+                    out.push_str(&piece);
+                    column += 1 + width(&piece);
+                } else {
+                    let start = inner * INDENT.len() + width(operator) + 1;
+                    let operand = layout(operand, start, inner);
+
+                    out.push_str(&format!("\n{}{} {}", INDENT.repeat(inner), operator, operand));
+                    column = end_column(start, &operand);
+                }
+            }
+
+            out
+        },
+        Expr::Grouping(g) => format!("({})", layout(&g.0, column + 1, indent)),
+        Expr::Assign(a) => {
+            let prefix = format!("{} = ", a.name.lexeme());
+            format!("{}{}", prefix, layout(&a.value, column + width(&prefix), indent))
+        },
+        _ => flat,
+    }
+}
+
+/// How many columns `text` takes up, counting characters rather than bytes.
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// The column `text` ends at when it starts at `column`.
+fn end_column(column: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(i) => width(&text[i + 1..]),
+        None => column + width(text),
+    }
+}
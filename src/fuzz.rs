@@ -0,0 +1,198 @@
+//! # Fuzzing
+//!
+//! Entry points for fuzzers, each taking arbitrary bytes through one more stage
+//! of the pipeline: scanning, parsing, then interpreting. Whatever the input,
+//! none of them may panic. The targets under `fuzz/` hand these to libFuzzer.
+//!
+//! For fuzzing without libFuzzer there is also a deterministic generator of
+//! random programs, which `rlox fuzz` and the test suite run. Most of what it
+//! generates is valid Lox, so it gets past the parser and into the interpreter,
+//! with some of it mangled to exercise the error paths too.
+
+use std::io;
+use std::panic;
+use crate::cst;
+use crate::interpreter::Interpreter;
+use crate::lox::EX_SOFTWARE;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+
+pub fn scan(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+
+    Scanner::new(&source).scan_tokens_quietly();
+}
+
+pub fn parse(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    let (tokens, _) = Scanner::new(&source).scan_tokens_quietly();
+
+    let _ = Parser::new(tokens.clone()).parse();
+    let _ = Parser::new(tokens).parse_expression();
+
+    // The lossless tree has to give back exactly what it was made from
+    assert_eq!(cst::parse(&source).to_string(), source);
+}
+
+pub fn interpret(data: &[u8]) {
+    let source = String::from_utf8_lossy(data);
+    let (tokens, _) = Scanner::new(&source).scan_tokens_quietly();
+
+    let parsed = Parser::new(tokens).parse();
+
+    // Run whatever parsed, even if the scanner complained
+    if let Ok(statements) = parsed {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(io::sink()));
+
+        let _ = interpreter.interpret(&statements);
+    }
+}
+
+/// Runs `runs` generated programs, starting from `seed`, through every stage.
+/// Stops at the first one that panics, printing its seed and source, and
+/// returns the exit code the process should use.
+pub fn run(runs: u64, seed: u64) -> i32 {
+    for seed in seed..seed.saturating_add(runs) {
+        let program = Generator::new(seed).program();
+
+        let result = panic::catch_unwind(|| {
+            scan(program.as_bytes());
+            parse(program.as_bytes());
+            interpret(program.as_bytes());
+        });
+
+        if result.is_err() {
+            eprintln!("Seed {} panicked on this program:\n{}", seed, program);
+            return EX_SOFTWARE;
+        }
+    }
+
+    println!("{} programs ran without panicking", runs);
+    0
+}
+
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "%", "~/", "**", "==", "!=", "<", "<=", ">", ">=",
+    "&", "|", "^", "<<", ">>",
+];
+
+const NAMES: &[&str] = &["a", "b", "c", "_d", "π", "undefined"];
+
+const LITERALS: &[&str] = &[
+    "0", "1", "2.5", "-0", "1e3", "123456789012345678901234567890", "0.1",
+    "0xFF", "0o17", "0b1010", "1_000.5e-3", "2E+8",
+    "\"\"", "\"lox\"", "\"ünï\"", "\"two\nlines\"", "\"\\t\\\"\\x41\\u{1F600}\"",
+    "\"a${1 + 2}b\"", "\"${a} and ${\"${b}\"}\"", "true", "false", "nil",
+];
+
+// What a mangled program gets spliced into it
+const JUNK: &[&str] = &[
+    "(", ")", "{", "}", ";", ",", ".", "=", "!", "?", ":", "\"", "//", "/", "\n", "@", "#", "é", "\0",
+    "%", "&", "|", "^", "~", "*", "<", ">", "~/",
+    "var", "print", "class", "fun", "return", "1.", ".5", "9999999999999999999999",
+    "\\", "\\x", "\\u{", "${", "\\$", "/*", "*/", "0x", "e", "_",
+    "\u{FEFF}", "\u{A0}", "\u{200B}", "\u{2028}", "ü", "😀",
+];
+
+/// A small, seeded source of random programs. The same seed always gives the same program.
+pub struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator { state: seed }
+    }
+
+    /// The next number from a splitmix64 sequence.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[self.below(choices.len())]
+    }
+
+    pub fn program(&mut self) -> String {
+        let mut program = String::new();
+
+        // Define most of the names up front so fewer programs stop at the first variable
+        for name in &NAMES[..NAMES.len() - 1] {
+            program.push_str(&format!("var {} = {};\n", name, self.pick(LITERALS)));
+        }
+
+        for _ in 0..1 + self.below(12) {
+            program.push_str(&self.statement());
+            program.push('\n');
+        }
+
+        // A quarter of the programs get mangled
+        if self.below(4) == 0 {
+            program = self.mangle(&program);
+        }
+
+        program
+    }
+
+    fn statement(&mut self) -> String {
+        match self.below(3) {
+            0 => format!("var {} = {};", self.pick(NAMES), self.expression(0)),
+            1 => format!("print {};", self.expression(0)),
+            _ => format!("{};", self.expression(0)),
+        }
+    }
+
+    fn expression(&mut self, depth: usize) -> String {
+        // Deeper expressions are more and more likely to stop at a leaf
+        let choice = if self.below(6) < depth { self.below(2) } else { self.below(9) };
+
+        match choice {
+            0 => String::from(self.pick(LITERALS)),
+            1 => String::from(self.pick(NAMES)),
+            2 | 3 => format!("{} {} {}", self.expression(depth + 1), self.pick(OPERATORS), self.expression(depth + 1)),
+            4 => format!("{}{}", self.pick(&["-", "!", "~"]), self.expression(depth + 1)),
+            // Only a variable can be assigned to, and only outside other operators
+            6 if depth == 0 => format!("{} = {}", self.pick(NAMES), self.expression(depth + 1)),
+            7 => format!("{} ? {} : {}", self.expression(depth + 1), self.expression(depth + 1), self.expression(depth + 1)),
+            8 => format!("({}, {})", self.expression(depth + 1), self.expression(depth + 1)),
+            _ => format!("({})", self.expression(depth + 1)),
+        }
+    }
+
+    /// Deletes, duplicates and splices junk into parts of `program`.
+    fn mangle(&mut self, program: &str) -> String {
+        let mut chars: Vec<char> = program.chars().collect();
+
+        for _ in 0..1 + self.below(4) {
+            let at = self.below(chars.len() + 1);
+
+            match self.below(3) {
+                0 if at < chars.len() => {
+                    let end = (at + 1 + self.below(8)).min(chars.len());
+                    chars.drain(at..end);
+                },
+                1 => {
+                    let end = (at + self.below(16)).min(chars.len());
+                    let copy: Vec<char> = chars[at..end].to_vec();
+                    chars.splice(at..at, copy);
+                },
+                _ => {
+                    let junk: Vec<char> = self.pick(JUNK).chars().collect();
+                    chars.splice(at..at, junk);
+                },
+            }
+        }
+
+        chars.into_iter().collect()
+    }
+}
@@ -1,68 +1,266 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
 use crate::loxerror::LoxError;
 use crate::loxvalue::LoxValue;
-use crate::expr::{Expr, UnaryExpr, LiteralExpr, BinaryExpr, GroupingExpr};
+use crate::environment::Environment;
+use crate::expr::{Expr, UnaryExpr, LiteralExpr, BinaryExpr, GroupingExpr, VariableExpr, AssignExpr, InterpolationExpr, InterpolationPart, ConditionalExpr, CommaExpr};
+use crate::stmt::{Stmt, VarStmt};
 use crate::token::{TokenType::*, Token};
 
+/// Why a program stopped before it finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeErrorKind {
+    // The program did something it can't
+    Error,
+    // A hook stopped it, like a debugger whose user quit
+    Stopped,
+}
+
 pub struct RuntimeError {
+    kind: RuntimeErrorKind,
     message: String,
-    token: Token,
+    line: usize,
 }
 
 impl RuntimeError {
     pub fn new(token: Token, msg: &str) -> Self {
-        Self { token: token, message: String::from(msg) }
+        Self::at_line(token.line, msg)
+    }
+
+    pub fn at_line(line: usize, msg: &str) -> Self {
+        Self { kind: RuntimeErrorKind::Error, line, message: String::from(msg) }
+    }
+
+    /// Stops the program at `line` without blaming it.
+    pub fn stopped(line: usize, msg: &str) -> Self {
+        Self { kind: RuntimeErrorKind::Stopped, line, message: String::from(msg) }
+    }
+
+    pub fn kind(&self) -> RuntimeErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
     }
 }
 
 impl From<RuntimeError> for LoxError {
     fn from(error: RuntimeError) -> Self {
-        let msg = format!("{}\n[line {}]", error.message, error.token.line);
+        let msg = format!("{}\n[line {}]", error.message, error.line);
         LoxError::new(&msg)
     }
 }
 
-pub struct Interpreter;
+/// Lets tools like the debugger watch a program run. The interpreter calls the
+/// hook before it executes each statement and before it evaluates each
+/// expression, sub-expressions included, and tells it which way each branch
+/// goes. Returning an error stops the program.
+/// The hook is detached while it runs, so anything it evaluates with the
+/// interpreter it is given won't call back into it.
+pub trait Hook {
+    fn before_statement(&mut self, _interpreter: &mut Interpreter, _statement: &Stmt) -> Result<(), RuntimeError> {
+        Ok(())
+    }
 
+    fn before_expression(&mut self, _interpreter: &mut Interpreter, _expression: &Expr) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
+    /// `token` is the one that decides the branch, like a condition's operator.
+    fn branch(&mut self, _interpreter: &mut Interpreter, _token: &Token, _taken: bool) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+/// An entry in the call stack.
+pub struct Frame {
+    pub name: String,
+    // The line of the statement the frame is executing
+    pub line: usize,
+}
+
+impl Frame {
+    pub fn new(name: &str) -> Self {
+        Frame { name: String::from(name), line: 0 }
+    }
+}
+
+/// Evaluates programs. The interpreter owns the global environment, so
+/// anything a program defines stays defined for later calls to `interpret`.
+pub struct Interpreter {
+    pub environment: Environment,
+    /// The innermost frame is last. Until Lox has functions there is only the script's.
+    pub frames: Vec<Frame>,
+    hook: Option<Box<dyn Hook>>,
+    // Where `print` writes to
+    output: Box<dyn Write>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
 
 impl Interpreter {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            environment: Environment::new(),
+            frames: vec![Frame::new("<script>")],
+            hook: None,
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Sends what the program prints somewhere other than stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hook = Some(hook);
+    }
+
+    pub fn take_hook(&mut self) -> Option<Box<dyn Hook>> {
+        self.hook.take()
+    }
+
+    fn before_statement(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.line = statement.line();
+        }
+
+        // The hook is moved out while it runs so it can look at the interpreter
+        match self.hook.take() {
+            Some(mut hook) => {
+                let result = hook.before_statement(self, statement);
+                self.hook = Some(hook);
+                result
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn before_expression(&mut self, expression: &Expr) -> Result<(), RuntimeError> {
+        match self.hook.take() {
+            Some(mut hook) => {
+                let result = hook.before_expression(self, expression);
+                self.hook = Some(hook);
+                result
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Constructs that choose between two paths call this once they have chosen.
+    pub fn branch(&mut self, token: &Token, taken: bool) -> Result<(), RuntimeError> {
+        match self.hook.take() {
+            Some(mut hook) => {
+                let result = hook.branch(self, token, taken);
+                self.hook = Some(hook);
+                result
+            },
+            None => Ok(()),
+        }
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        for statement in statements {
+            self.execute(statement)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single statement. Expression statements hand back the value they
+    /// evaluated to so the REPL can echo it; every other statement yields `None`.
+    pub fn execute(&mut self, statement: &Stmt) -> Result<Option<LoxValue>, RuntimeError> {
+        statement.execute(self)
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<LoxValue, RuntimeError> {
+        expr.interpret(self)
+    }
+}
+
+pub trait Execute {
+    fn execute(&self, interpreter: &mut Interpreter) -> Result<Option<LoxValue>, RuntimeError>;
+}
+
+impl Execute for Stmt<'_> {
+    fn execute(&self, interpreter: &mut Interpreter) -> Result<Option<LoxValue>, RuntimeError> {
+        interpreter.before_statement(self)?;
+
+        match self {
+            Stmt::Expression(e) => Ok(Some(e.expression.interpret(interpreter)?)),
+            Stmt::Print(p) => {
+                let value = p.expression.interpret(interpreter)?;
+
+                writeln!(interpreter.output, "{}", value)
+                    .map_err(|e| RuntimeError::new(p.keyword.clone(), &format!("Could not print: {}", e)))?;
+
+                Ok(None)
+            },
+            Stmt::Var(v) => v.execute(interpreter),
+        }
     }
+}
+
+impl Execute for VarStmt<'_> {
+    fn execute(&self, interpreter: &mut Interpreter) -> Result<Option<LoxValue>, RuntimeError> {
+        let value = match &self.initializer {
+            Some(i) => i.interpret(interpreter)?,
+            None => LoxValue::LoxNil,
+        };
 
-    pub fn interpret(&self, expr: Expr) -> Result<LoxValue, RuntimeError> {
-        expr.interpret()
+        interpreter.environment.define(self.name.lexeme(), value);
+
+        Ok(None)
     }
 }
 
 pub trait Interpret {
-    fn interpret(&self) -> Result<LoxValue, RuntimeError>;
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError>;
 }
 
-impl Interpret for Expr {
-    fn interpret(&self) -> Result<LoxValue, RuntimeError> {
+impl Interpret for Expr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        interpreter.before_expression(self)?;
+
         match self {
-            Expr::Unary(u) => u.interpret(),
-            Expr::Binary(b) => b.interpret(),
-            Expr::Literal(l) => l.interpret(),
-            Expr::Grouping(g) => g.interpret(),
+            Expr::Unary(u) => u.interpret(interpreter),
+            Expr::Binary(b) => b.interpret(interpreter),
+            Expr::Literal(l) => l.interpret(interpreter),
+            Expr::Grouping(g) => g.interpret(interpreter),
+            Expr::Variable(v) => v.interpret(interpreter),
+            Expr::Assign(a) => a.interpret(interpreter),
+            Expr::Interpolation(i) => i.interpret(interpreter),
+            Expr::Conditional(c) => c.interpret(interpreter),
+            Expr::Comma(c) => c.interpret(interpreter),
         }
     }
 }
 
 impl Interpret for LiteralExpr {
-    fn interpret(&self) -> Result<LoxValue, RuntimeError> {
+    fn interpret(&self, _interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
         match self {
             LiteralExpr::Nil => Ok(LoxValue::LoxNil),
             LiteralExpr::Bool(b) => Ok(LoxValue::LoxBool(*b)),
-            LiteralExpr::Number(n) => Ok(LoxValue::LoxNumber(*n)),
+            LiteralExpr::Number(n, _) => Ok(LoxValue::LoxNumber(*n)),
             LiteralExpr::String(s) => Ok(LoxValue::LoxString(s.clone()))
         }
     }
 }
 
-impl Interpret for UnaryExpr {
-    fn interpret(&self) -> Result<LoxValue, RuntimeError> {
-        let value = self.operand.interpret()?;
+impl Interpret for UnaryExpr<'_> {
+    #[allow(clippy::neg_multiply)]
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        let value = self.operand.interpret(interpreter)?;
 
         match &self.operator {
             t @ Token { token_type: MINUS, ..} => {
@@ -72,15 +270,24 @@ impl Interpret for UnaryExpr {
                 }
             },
             Token { token_type: BANG, ..} => Ok(LoxValue::LoxBool(!is_truthy(value))),
+            t @ Token { token_type: TILDE, ..} => {
+                match value {
+                    LoxValue::LoxNumber(n) => match integer(n) {
+                        Some(i) => Ok(LoxValue::LoxNumber(!i as f64)),
+                        None => Err(RuntimeError::new(t.clone(), "Operand must be an integer")),
+                    },
+                    _ => Err(RuntimeError::new(t.clone(), "Operand must be an integer"))
+                }
+            },
             _ => unreachable!(),
         }
     }
 }
 
-impl Interpret for BinaryExpr {
-    fn interpret(&self) -> Result<LoxValue, RuntimeError> {
-        let left = self.left.interpret()?;
-        let right = self.right.interpret()?;
+impl Interpret for BinaryExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        let left = self.left.interpret(interpreter)?;
+        let right = self.right.interpret(interpreter)?;
 
         match &self.operator {
             t @ Token { token_type: PLUS, ..} => {
@@ -108,6 +315,50 @@ impl Interpret for BinaryExpr {
                     _ => Err(RuntimeError::new(t.clone(), "Operands must be numbers")),
                 }
             },
+            t @ Token { token_type: PERCENT, ..} => {
+                match (left, right) {
+                    // The remainder takes the sign of the divisor, to go with `~/` rounding down
+                    (LoxValue::LoxNumber(l), LoxValue::LoxNumber(r)) => {
+                        let remainder = l % r;
+                        let floored = if remainder != 0.0 && (remainder < 0.0) != (r < 0.0) { remainder + r } else { remainder };
+                        Ok(LoxValue::LoxNumber(floored))
+                    },
+                    _ => Err(RuntimeError::new(t.clone(), "Operands must be numbers")),
+                }
+            },
+            t @ Token { token_type: TILDE_SLASH, ..} => {
+                match (left, right) {
+                    (LoxValue::LoxNumber(l), LoxValue::LoxNumber(r)) => Ok(LoxValue::LoxNumber((l / r).floor())),
+                    _ => Err(RuntimeError::new(t.clone(), "Operands must be numbers")),
+                }
+            },
+            t @ Token { token_type: STAR_STAR, ..} => {
+                match (left, right) {
+                    (LoxValue::LoxNumber(l), LoxValue::LoxNumber(r)) => Ok(LoxValue::LoxNumber(l.powf(r))),
+                    _ => Err(RuntimeError::new(t.clone(), "Operands must be numbers")),
+                }
+            },
+            t @ Token { token_type: AMPERSAND | PIPE | CARET | LESS_LESS | GREATER_GREATER, ..} => {
+                let (l, r) = match (left, right) {
+                    (LoxValue::LoxNumber(l), LoxValue::LoxNumber(r)) => match (integer(l), integer(r)) {
+                        (Some(l), Some(r)) => (l, r),
+                        _ => return Err(RuntimeError::new(t.clone(), "Operands must be integers")),
+                    },
+                    _ => return Err(RuntimeError::new(t.clone(), "Operands must be integers")),
+                };
+
+                let value = match t.token_type {
+                    AMPERSAND => l & r,
+                    PIPE => l | r,
+                    CARET => l ^ r,
+                    _ if r < 0 => return Err(RuntimeError::new(t.clone(), "Shift count must not be negative")),
+                    // Shifting every bit out leaves nothing, or just the sign
+                    LESS_LESS => u32::try_from(r).ok().and_then(|r| l.checked_shl(r)).unwrap_or(0),
+                    _ => l >> r.min(63),
+                };
+
+                Ok(LoxValue::LoxNumber(value as f64))
+            },
             t @ Token { token_type: GREATER, ..} => {
                 match (left, right) {
                     (LoxValue::LoxNumber(l), LoxValue::LoxNumber(r)) => Ok(LoxValue::LoxBool(l > r)),
@@ -140,13 +391,73 @@ impl Interpret for BinaryExpr {
 }
 
 
-impl Interpret for GroupingExpr {
-    fn interpret(&self) -> Result<LoxValue, RuntimeError> {
-        (*self.0).interpret()
+impl Interpret for GroupingExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        (*self.0).interpret(interpreter)
+    }
+}
+
+impl Interpret for VariableExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        interpreter.environment.get(&self.name)
+    }
+}
+
+impl Interpret for AssignExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        let value = self.value.interpret(interpreter)?;
+
+        interpreter.environment.assign(&self.name, value.clone())?;
+
+        Ok(value)
+    }
+}
+
+impl Interpret for InterpolationExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        let mut string = String::new();
+
+        for part in &self.parts {
+            match part {
+                InterpolationPart::Text(t) => string.push_str(t),
+                // Any value can be spliced in, written the way `print` would write it
+                InterpolationPart::Expr(e) => string.push_str(&e.interpret(interpreter)?.to_string()),
+            }
+        }
+
+        Ok(LoxValue::LoxString(string))
+    }
+}
+
+impl Interpret for ConditionalExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        let taken = is_truthy(self.condition.interpret(interpreter)?);
+
+        interpreter.branch(&self.question, taken)?;
+
+        if taken {
+            self.then_branch.interpret(interpreter)
+        } else {
+            self.else_branch.interpret(interpreter)
+        }
     }
 }
 
+impl Interpret for CommaExpr<'_> {
+    fn interpret(&self, interpreter: &mut Interpreter) -> Result<LoxValue, RuntimeError> {
+        self.left.interpret(interpreter)?;
+        self.right.interpret(interpreter)
+    }
+}
+
+/// The number as an integer, if it is a whole number the bitwise operators can
+/// work on.
+fn integer(n: f64) -> Option<i64> {
+    // i64::MAX isn't representable, and rounds up to 2^63
+    (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+}
 
+#[allow(clippy::bool_comparison, clippy::redundant_guards)]
 fn is_truthy(v: LoxValue) -> bool {
     match v {
         LoxValue::LoxNil => false,
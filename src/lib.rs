@@ -1,8 +1,21 @@
 pub mod lox;
+pub mod cli;
+pub mod repl;
 pub mod loxerror;
 pub mod loxvalue;
 pub mod scanner;
 pub mod parser;
 pub mod interpreter;
+pub mod environment;
 pub mod token;
-pub mod expr;
\ No newline at end of file
+pub mod expr;
+pub mod stmt;
+pub mod formatter;
+pub mod cst;
+pub mod lsp;
+pub mod debugger;
+pub mod dap;
+pub mod profiler;
+pub mod coverage;
+pub mod testrunner;
+pub mod fuzz;
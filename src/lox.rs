@@ -1,67 +1,185 @@
 use std::fs;
-use std::io::{Write, stdin, stdout};
-use crate::loxerror::{set_error, get_error, LoxError};
+use std::time::Instant;
+use crate::loxerror::{set_error, get_error, set_runtime_error, get_runtime_error, LoxError};
 use crate::scanner::Scanner;
-use crate::parser::Parser;
-use crate::interpreter::Interpreter;
+use crate::parser::{Parser, ParserError};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::stmt::Stmt;
+use crate::token::Token;
+use crate::repl::{self, Command, Input};
+
+// Exit codes, following the BSD sysexits.h conventions
+pub const EX_USAGE: i32 = 64;
+pub const EX_DATAERR: i32 = 65;
+pub const EX_NOINPUT: i32 = 66;
+pub const EX_SOFTWARE: i32 = 70;
+pub const EX_IOERR: i32 = 74;
 
 pub struct Lox;
 
 impl Lox {
-    pub fn run_file(path: &str) -> Result<(), LoxError> {
-        let c = match fs::read_to_string(path) {
-            Ok(s) => s,
-            Err(e) => panic!("Could not read file {}: {}", path, e) 
-        };
-    
-        Lox::run(&c)?;
+    /// Forgets the errors of earlier runs, so they don't fail the next one.
+    pub fn clear_errors() {
+        set_error(false);
+        set_runtime_error(false);
+    }
+
+    /// Scans and parses `source`, reporting every error found along the way.
+    /// Returns the program only if there weren't any.
+    pub fn parse(source: &str) -> Option<Vec<Stmt<'_>>> {
+        Lox::clear_errors();
+
+        match Parser::new(Scanner::new(source).reporting()).parse() {
+            Ok(statements) if !get_error() => Some(statements),
+            Ok(_) => None,
+            Err(errors) => {
+                eprintln!("{}", LoxError::from(errors));
+                None
+            }
+        }
+    }
+
+    /// Runs a whole program and returns the exit code the process should use.
+    pub fn run_source(source: &str) -> i32 {
+        Lox::clear_errors();
+
+        if let Err(e) = Lox::run(source, &mut Interpreter::new()) {
+            eprintln!("{}", e);
+        }
 
         // Exit like a good citizen
-        if get_error() { std::process::exit(65); }
+        if get_error() { return EX_DATAERR; }
+        if get_runtime_error() { return EX_SOFTWARE; }
     
-        Ok(())
+        0
     }
     
     pub fn run_prompt() -> Result<(), LoxError> {
+        // One interpreter for the whole session so that variables
+        // declared on one line can be used on the next.
+        let mut interpreter = Interpreter::new();
+
+        let mut input = Input::new();
+
         loop {
-            print!("> ");
+            input.set_globals(interpreter.environment.values().into_iter().map(|(n, _)| n.clone()).collect());
+
+            let line = match input.read_line("> ") {
+                Ok(Some(line)) => line,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(LoxError::new(&format!("Could not read input: {}", e))),
+            };
+
+            let result = match Command::parse(&line) {
+                Some(Ok(Command::Quit)) => return Ok(()),
+                Some(Ok(command)) => Lox::run_command(command, &mut interpreter),
+                Some(Err(msg)) => Err(LoxError::new(&msg)),
+                None => Lox::run_line(&line, &mut interpreter),
+            };
 
-            stdout().flush().unwrap(); // Not sure what kind of errors can happen here.
-    
-            let mut line = String::new();
-    
-            match stdin().read_line(&mut line) {
-                Ok(_) => {},
-                Err(_) => { println!("Could not read input. Try again."); continue; }
-            }
-            
             // Until we seriously tackle error handling and synchronization,
             // discard any errors and keep looping.
-            match Lox::run(&line) {
-                Ok(_) => {},
-                Err(e) => println!("{}", e)
-            };
+            if let Err(e) = result {
+                println!("{}", e);
+            }
 
             // Interactive mode shouldn't fail if the user makes a mistake
-            set_error(false);
+            Lox::clear_errors();
         }
     }
     
-    fn run(source: &str) -> Result<(), LoxError> {
+    fn run_command(command: Command, interpreter: &mut Interpreter) -> Result<(), LoxError> {
+        match command {
+            Command::Help => println!("{}", repl::HELP),
+            Command::Quit => {},
+            Command::Load(path) => {
+                let source = fs::read_to_string(path)
+                    .map_err(|e| LoxError::new(&format!("Could not read file {}: {}", path, e)))?;
+
+                Lox::run(&source, interpreter)?;
+            },
+            Command::Reset => *interpreter = Interpreter::new(),
+            Command::Env => {
+                for (name, value) in interpreter.environment.values() {
+                    println!("{} = {}", name, value);
+                }
+            },
+            Command::Tokens(source) => {
+                for token in Scanner::new(source).scan_tokens() {
+                    println!("{}", token);
+                }
+            },
+            Command::Ast(source) => {
+                let tokens = Scanner::new(source).scan_tokens();
+
+                match Parser::new(tokens.clone()).parse_expression() {
+                    Ok(expression) => println!("{}", expression),
+                    Err(_) => {
+                        for statement in Parser::new(tokens).parse()? {
+                            println!("{}", statement);
+                        }
+                    }
+                }
+            },
+            Command::Time(source) => {
+                let start = Instant::now();
+                let result = Lox::run_line(source, interpreter);
+                println!("Took {:?}", start.elapsed());
+                result?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn run(source: &str, interpreter: &mut Interpreter) -> Result<(), LoxError> {
+        let mut parser = Parser::new(Scanner::new(source).reporting());
+
+        let statements = parser.parse().map_err(Lox::compile_error)?;
+
+        // The scanner reports its errors as it goes; don't run a broken program
+        if get_error() { return Ok(()); }
+
+        interpreter.interpret(&statements).map_err(Lox::runtime_error)?;
+
+        Ok(())
+    }
+
+    /// Runs one line of REPL input, echoing the value of any bare expression.
+    /// The line may be a lone expression without a trailing `;`.
+    fn run_line(source: &str, interpreter: &mut Interpreter) -> Result<(), LoxError> {
         let scanner = Scanner::new(source);
 
-        let tokens = scanner.scan_tokens();
+        let tokens: Vec<Token> = scanner.scan_tokens();
 
-        let mut parser = Parser::new(tokens);
+        if get_error() { return Ok(()); }
 
-        let expression = parser.parse()?;
+        if let Ok(expression) = Parser::new(tokens.clone()).parse_expression() {
+            let value = interpreter.evaluate(&expression).map_err(Lox::runtime_error)?;
 
-        let interpreter = Interpreter::new();
+            println!("{}", value);
 
-        let value = interpreter.interpret(expression)?;
+            return Ok(());
+        }
 
-        println!("{}", value);
+        let statements = Parser::new(tokens).parse().map_err(Lox::compile_error)?;
+
+        for statement in &statements {
+            if let Some(value) = interpreter.execute(statement).map_err(Lox::runtime_error)? {
+                println!("{}", value);
+            }
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn compile_error(error: Vec<ParserError>) -> LoxError {
+        set_error(true);
+        LoxError::from(error)
+    }
+
+    fn runtime_error(error: RuntimeError) -> LoxError {
+        set_runtime_error(true);
+        LoxError::from(error)
+    }
+}
@@ -1,11 +1,16 @@
 //! # Lox error-handling
 //! 
 
+use std::cell::Cell;
 use std::io::{stdout, Write};
 use std::fmt;
 
-static mut HAD_ERROR: bool = false;
-static mut HAD_RUNTIME_ERROR: bool = false;
+// Lox runs on one thread. Keeping the flags per thread means tests running
+// programs side by side don't see each other's errors.
+thread_local! {
+    static HAD_ERROR: Cell<bool> = const { Cell::new(false) };
+    static HAD_RUNTIME_ERROR: Cell<bool> = const { Cell::new(false) };
+}
 
 #[derive(Debug, Clone)]
 pub struct LoxError{
@@ -34,33 +39,21 @@ pub fn error(line: usize, message: &str) {
 
 pub fn report(line: usize, location: &str, message: &str) {
     eprintln!("[line {}] Error {}: {}", line, location, message);
-    stdout().flush().unwrap();
+    let _ = stdout().flush();
 }
 
 pub fn get_error() -> bool {
-    unsafe {
-        HAD_ERROR
-    }
+    HAD_ERROR.with(Cell::get)
 }
 
 pub fn get_runtime_error() -> bool {
-    unsafe {
-        HAD_RUNTIME_ERROR
-    }
+    HAD_RUNTIME_ERROR.with(Cell::get)
 }
 
 pub fn set_error(had_error: bool) {
-
-    // There will be no concurrent code touching this, so it's K
-    unsafe {
-        HAD_ERROR = had_error;
-    }
+    HAD_ERROR.with(|e| e.set(had_error));
 }
 
 pub fn set_runtime_error(had_runtime_error: bool) {
-
-    // There will be no concurrent code touching this, so it's K
-    unsafe {
-        HAD_RUNTIME_ERROR = had_runtime_error;
-    }
+    HAD_RUNTIME_ERROR.with(|e| e.set(had_runtime_error));
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std::fmt;
 
+#[derive(Debug, Clone)]
 pub enum LoxValue {
     LoxNumber(f64),
     LoxString(String),
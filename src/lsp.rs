@@ -0,0 +1,479 @@
+//! # Lox Language Server
+//!
+//! A Language Server Protocol server speaking JSON-RPC over stdin and stdout,
+//! so editors can show errors, hovers, definitions, references, document
+//! symbols and completions for Lox files.
+//!
+//! Documents are synced in full on every change. Since the language only has
+//! global variables so far, every use of a name refers to the same variable
+//! and its definitions are the `var` declarations of that name.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use serde_json::{json, Value};
+use crate::loxerror::LoxError;
+use crate::parser::Parser;
+use crate::scanner::{Scanner, KEY_WORDS};
+use crate::token::{Token, TokenType, StringPart};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP enumerations
+const SEVERITY_ERROR: u8 = 1;
+const SYMBOL_KIND_VARIABLE: u8 = 13;
+const COMPLETION_KIND_VARIABLE: u8 = 6;
+const COMPLETION_KIND_KEYWORD: u8 = 14;
+
+// Bigger than any message a client has reason to send, so a bad header can't
+// make us allocate whatever it likes
+const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Serves requests from stdin until the client sends `exit`, returning the exit code.
+pub fn run() -> i32 {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    match serve(&mut input, &mut output) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Language server failed: {}", e);
+            crate::lox::EX_IOERR
+        }
+    }
+}
+
+/// Reads messages from `input` and writes replies to `output` until the client
+/// sends `exit` or closes the input.
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<i32> {
+    let mut server = Server::new();
+
+    while let Some(message) = read_message(input)? {
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            // Without a readable message there's no id to answer to
+            Err(e) => vec![json!({ "jsonrpc": "2.0", "id": null, "error": { "code": PARSE_ERROR, "message": e } })],
+        };
+
+        for reply in replies {
+            write_message(output, &reply)?;
+        }
+
+        if let Some(code) = server.exit_code {
+            return Ok(code);
+        }
+    }
+
+    // The client went away without saying goodbye
+    Ok(1)
+}
+
+/// Reads one `Content-Length` framed message, or `None` at the end of the input.
+/// A body that isn't JSON, or is too large to read, is skipped and described
+/// instead, so the next message can still be read.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, String>>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header"))?;
+
+    if length > MAX_CONTENT_LENGTH {
+        io::copy(&mut input.take(length as u64), &mut io::sink())?;
+        return Ok(Some(Err(format!("Message of {} bytes is too large", length))));
+    }
+
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {}", e))))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    /// Set once the client has asked us to exit
+    pub exit_code: Option<i32>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+            exit_code: None,
+        }
+    }
+
+    /// Handles one incoming message, returning the messages to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, params),
+        };
+
+        let result = match method {
+            "initialize" => Ok(initialize_result()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            },
+            "textDocument/hover" => self.with_position(params, Document::hover),
+            "textDocument/definition" => self.with_position(params, Document::definition),
+            "textDocument/references" => {
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                self.with_position(params, |d, t, i| d.references(t, i, include_declaration))
+            },
+            "textDocument/documentSymbol" => self.document(params).map(Document::symbols),
+            "textDocument/completion" => self.document(params).map(Document::completions),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        };
+
+        let reply = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        };
+
+        vec![reply]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+
+        match method {
+            "exit" => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                Vec::new()
+            },
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.open(uri, text)
+            },
+            "textDocument/didChange" => {
+                // We only ask for full syncs, so the last change holds the whole text
+                match params["contentChanges"].as_array().and_then(|c| c.last()) {
+                    Some(change) => self.open(uri, change["text"].as_str().unwrap_or("")),
+                    None => Vec::new(),
+                }
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            },
+            // Everything else, like `initialized`, needs no reply
+            _ => Vec::new(),
+        }
+    }
+
+    fn open(&mut self, uri: String, text: &str) -> Vec<Value> {
+        let document = Document::new(&uri, text);
+        let diagnostics = publish_diagnostics(&uri, document.diagnostics.clone());
+
+        self.documents.insert(uri, document);
+
+        vec![diagnostics]
+    }
+
+    fn document(&self, params: &Value) -> Result<&Document, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+
+        self.documents.get(uri).ok_or_else(|| (INVALID_PARAMS, format!("Unknown document {}", uri)))
+    }
+
+    /// Runs `f` on the token under the cursor. Positions between tokens give `null`.
+    fn with_position<F>(&self, params: &Value, f: F) -> Result<Value, (i64, String)>
+    where F: Fn(&Document, &[Token], usize) -> Value {
+        let document = self.document(params)?;
+        let position = &params["position"];
+
+        let (line, character) = match (position["line"].as_u64(), position["character"].as_u64()) {
+            (Some(l), Some(c)) => (l as usize, c as usize),
+            _ => return Err((INVALID_PARAMS, String::from("Missing position"))),
+        };
+
+        let tokens = document.tokens();
+
+        Ok(document.token_at(&tokens, line, character).map_or(Value::Null, |i| f(document, &tokens, i)))
+    }
+}
+
+fn initialize_result() -> Value {
+    json!({
+        "capabilities": {
+            // Full document sync
+            "textDocumentSync": 1,
+            "hoverProvider": true,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "documentSymbolProvider": true,
+            "completionProvider": {},
+        },
+        "serverInfo": { "name": "rlox", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Replaces each interpolated string with the tokens of the code in it, so
+/// variables used inside `${...}` can be looked up like any others.
+fn flatten<'a>(tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+    let mut flat = Vec::new();
+
+    for token in tokens {
+        match &token.token_type {
+            TokenType::INTERPOLATION(parts) => {
+                for part in parts {
+                    if let StringPart::Code(code) = part {
+                        // Leave out the EOF each one ends with
+                        flat.extend(flatten(&code[..code.len() - 1]));
+                    }
+                }
+            },
+            _ => flat.push(token.clone()),
+        }
+    }
+
+    flat
+}
+
+/// An open file and what we learned from scanning and parsing it. Tokens
+/// borrow the text they were scanned from, so they're scanned again as needed.
+struct Document {
+    uri: String,
+    text: String,
+    lines: Vec<String>,
+    diagnostics: Vec<Value>,
+}
+
+impl Document {
+    fn new(uri: &str, text: &str) -> Self {
+        let lines: Vec<String> = text.split('\n').map(String::from).collect();
+        let (tokens, scan_errors) = Scanner::new(text).scan_tokens_quietly();
+        let eof = tokens.last().cloned();
+
+        let mut document = Document { uri: String::from(uri), text: String::from(text), lines, diagnostics: Vec::new() };
+
+        for error in scan_errors {
+            let (line, start) = document.position(error.line, error.column);
+            let range = json!({
+                "start": { "line": line, "character": start },
+                "end": { "line": line, "character": start + 1 },
+            });
+
+            document.diagnostics.push(diagnostic(range, &error.message));
+        }
+
+        if let Err(errors) = Parser::new(tokens).parse() {
+            for error in errors {
+                // Running out of tokens can only happen at the very end
+                let range = match error.token().or(eof.as_ref()) {
+                    Some(t) => document.range(t),
+                    None => Value::Null,
+                };
+
+                document.diagnostics.push(diagnostic(range, &LoxError::from(error).to_string()));
+            }
+        }
+
+        document
+    }
+
+    /// The document's tokens, with interpolated strings flattened.
+    fn tokens(&self) -> Vec<Token<'_>> {
+        flatten(&Scanner::new(&self.text).scan_tokens_quietly().0)
+    }
+
+    fn hover(&self, tokens: &[Token], index: usize) -> Value {
+        let token = &tokens[index];
+
+        let description = match &token.token_type {
+            TokenType::IDENTIFIER => match Document::declarations(tokens, token.lexeme()).first() {
+                Some(d) => format!("```lox\nvar {}\n```\nGlobal variable, declared on line {}", token.lexeme(), tokens[*d].line),
+                None => format!("```lox\n{}\n```\nUndefined variable", token.lexeme()),
+            },
+            TokenType::NUMBER => format!("Number literal `{}`", token.number()),
+            TokenType::STRING => String::from("String literal"),
+            t if KEY_WORDS.iter().any(|(_, k)| k == t) => format!("Keyword `{}`", token.lexeme()),
+            _ => return Value::Null,
+        };
+
+        json!({
+            "contents": { "kind": "markdown", "value": description },
+            "range": self.range(token),
+        })
+    }
+
+    fn definition(&self, tokens: &[Token], index: usize) -> Value {
+        let token = &tokens[index];
+
+        if token.token_type != TokenType::IDENTIFIER {
+            return Value::Null;
+        }
+
+        Document::declarations(tokens, token.lexeme())
+            .into_iter()
+            .map(|d| self.location(&tokens[d]))
+            .collect()
+    }
+
+    fn references(&self, tokens: &[Token], index: usize, include_declaration: bool) -> Value {
+        let token = &tokens[index];
+
+        if token.token_type != TokenType::IDENTIFIER {
+            return Value::Null;
+        }
+
+        tokens.iter()
+            .enumerate()
+            .filter(|(i, t)| t.token_type == TokenType::IDENTIFIER && t.lexeme() == token.lexeme()
+                && (include_declaration || !Document::is_declaration(tokens, *i)))
+            .map(|(_, t)| self.location(t))
+            .collect()
+    }
+
+    fn symbols(&self) -> Value {
+        let tokens = self.tokens();
+
+        (0..tokens.len())
+            .filter(|i| Document::is_declaration(&tokens, *i))
+            .map(|i| {
+                let token = &tokens[i];
+                json!({
+                    "name": token.lexeme(),
+                    "kind": SYMBOL_KIND_VARIABLE,
+                    "range": self.range(token),
+                    "selectionRange": self.range(token),
+                })
+            })
+            .collect()
+    }
+
+    fn completions(&self) -> Value {
+        let tokens = self.tokens();
+
+        let keywords = KEY_WORDS.iter()
+            .map(|(k, _)| json!({ "label": k, "kind": COMPLETION_KIND_KEYWORD }));
+
+        let mut names: Vec<&str> = (0..tokens.len())
+            .filter(|i| Document::is_declaration(&tokens, *i))
+            .map(|i| tokens[i].lexeme())
+            .collect();
+
+        names.sort();
+        names.dedup();
+
+        let variables = names.into_iter()
+            .map(|n| json!({ "label": n, "kind": COMPLETION_KIND_VARIABLE }));
+
+        keywords.chain(variables).collect()
+    }
+
+    /// Whether the token at `index` is the name in a `var` declaration.
+    fn is_declaration(tokens: &[Token], index: usize) -> bool {
+        index > 0
+            && tokens[index].token_type == TokenType::IDENTIFIER
+            && tokens[index - 1].token_type == TokenType::VAR
+    }
+
+    fn declarations(tokens: &[Token], name: &str) -> Vec<usize> {
+        (0..tokens.len())
+            .filter(|i| Document::is_declaration(tokens, *i) && tokens[*i].lexeme() == name)
+            .collect()
+    }
+
+    /// Finds the token covering an LSP position.
+    fn token_at(&self, tokens: &[Token], line: usize, character: usize) -> Option<usize> {
+        tokens.iter().position(|t| {
+            let (start, end) = self.span(t);
+            start <= (line, character) && (line, character) < end
+        })
+    }
+
+    fn location(&self, token: &Token) -> Value {
+        json!({ "uri": self.uri, "range": self.range(token) })
+    }
+
+    fn range(&self, token: &Token) -> Value {
+        let (start, end) = self.span(token);
+
+        json!({
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        })
+    }
+
+    /// Where `token` starts and ends, as LSP (line, character) pairs.
+    fn span(&self, token: &Token) -> ((usize, usize), (usize, usize)) {
+        let start = self.position(token.line, token.column);
+
+        // Only strings can span several lines
+        let end = match token.lexeme().rsplit_once('\n') {
+            Some((before, last)) => (start.0 + before.matches('\n').count() + 1, last.encode_utf16().count()),
+            None => self.position(token.line, token.column + token.lexeme().chars().count()),
+        };
+
+        (start, end)
+    }
+
+    /// Converts our 1-based line and character column into an LSP position,
+    /// which is 0-based and counts UTF-16 code units.
+    fn position(&self, line: usize, column: usize) -> (usize, usize) {
+        let text = self.lines.get(line.saturating_sub(1)).map_or("", String::as_str);
+        let character = text.chars().take(column.saturating_sub(1)).map(char::len_utf16).sum();
+
+        (line.saturating_sub(1), character)
+    }
+}
+
+fn diagnostic(range: Value, message: &str) -> Value {
+    json!({
+        "range": range,
+        "severity": SEVERITY_ERROR,
+        "source": "rlox",
+        "message": message,
+    })
+}
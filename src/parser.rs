@@ -1,199 +1,439 @@
 //! # Lox Parser
 //! 
 
+use std::ops::RangeInclusive;
 use crate::loxerror;
-use crate::token::{Token, TokenType::*};
-use crate::expr::{Expr, UnaryExpr, LiteralExpr, BinaryExpr, GroupingExpr};
+use crate::token::{Token, TokenType, TokenType::*, StringPart};
+use crate::expr::{Expr, UnaryExpr, LiteralExpr, BinaryExpr, GroupingExpr, VariableExpr, AssignExpr, InterpolationExpr, InterpolationPart, ConditionalExpr, CommaExpr};
+use crate::stmt::{Stmt, VarStmt, PrintStmt, ExpressionStmt};
 
 
-pub struct ParserError {
-    token: Option<Token>,
+// How deeply expressions may nest before the parser gives up on them. Parsing,
+// evaluating and even dropping an expression recurse once per level, so without
+// a limit a long enough run of `(` would overflow the stack.
+pub(crate) const MAX_NESTING: usize = 100;
+
+/// What was wrong with the token the parser choked on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParserErrorKind {
+    // The grammar doesn't allow it there
+    UnexpectedToken,
+    // It opens one level of nesting more than `MAX_NESTING` allows
+    TooDeep,
+}
+
+pub struct ParserError<'a> {
+    kind: ParserErrorKind,
+    token: Option<Token<'a>>,
 }
 
-impl ParserError {
-    pub fn new(token: Option<Token>) -> Self {
-        Self { token }
+impl<'a> ParserError<'a> {
+    pub fn new(token: Option<Token<'a>>) -> Self {
+        Self { kind: ParserErrorKind::UnexpectedToken, token }
+    }
+
+    fn too_deep(token: Token<'a>) -> Self {
+        Self { kind: ParserErrorKind::TooDeep, token: Some(token) }
+    }
+
+    pub fn kind(&self) -> ParserErrorKind {
+        self.kind
+    }
+
+    /// The token the parser choked on, if it hadn't run out of tokens.
+    pub fn token(&self) -> Option<&Token<'a>> {
+        self.token.as_ref()
+    }
+
+    /// What was wrong with the token.
+    pub fn message(&self) -> &str {
+        match self.kind {
+            ParserErrorKind::UnexpectedToken => "Unexpected token",
+            ParserErrorKind::TooDeep => "Expression nests too deeply",
+        }
     }
 }
 
-impl From<ParserError> for loxerror::LoxError {
-    fn from(error: ParserError) -> Self {
-        match error.token {
-            Some(t) => {
-                let msg = format!("Parsing error: Unexpected token '{}'({:?}) at line {}", t.lexeme, t.token_type, t.line);
-                loxerror::LoxError::new(&msg)
+impl From<ParserError<'_>> for loxerror::LoxError {
+    fn from(error: ParserError<'_>) -> Self {
+        match (error.kind, &error.token) {
+            (ParserErrorKind::TooDeep, Some(t)) => {
+                loxerror::LoxError::new(&format!("Parsing error: {} at '{}' on line {}", error.message(), t.lexeme(), t.line))
             },
-            None => {
-                let msg = format!("Parsing error: Unexpectedly reached end of file");
+            (_, Some(t)) => {
+                let msg = format!("Parsing error: Unexpected token '{}'({:?}) at line {}", t.lexeme(), t.token_type, t.line);
                 loxerror::LoxError::new(&msg)
+            },
+            (_, None) => {
+                loxerror::LoxError::new("Parsing error: Unexpectedly reached end of file")
             }
         }
     }
 }
 
-pub struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
+impl From<Vec<ParserError<'_>>> for loxerror::LoxError {
+    fn from(errors: Vec<ParserError<'_>>) -> Self {
+        let messages: Vec<String> = errors.into_iter()
+            .map(|e| loxerror::LoxError::from(e).to_string())
+            .collect();
+
+        loxerror::LoxError::new(&messages.join("\n"))
+    }
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+/// How tightly an operator holds on to its operands, loosest first. Binary
+/// operators come after the conditional, in the order `TokenType::precedence`
+/// gives them.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Power {
+    None,
+    Comma,
+    Assignment,
+    Conditional,
+    Binary(u8),
+}
+
+// Parses an expression starting with the token it's given
+type Prefix<'a> = fn(&mut Parser<'a>, Token<'a>) -> Result<Expr<'a>, ParserError<'a>>;
+
+// Parses the rest of an expression whose operator is the token it's given
+type Infix<'a> = fn(&mut Parser<'a>, Expr<'a>, Token<'a>) -> Result<Expr<'a>, ParserError<'a>>;
+
+/// How the parser handles one type of token: at the start of an expression,
+/// after one, and how tightly it binds there.
+struct Rule<'a> {
+    prefix: Option<Prefix<'a>>,
+    infix: Option<Infix<'a>>,
+    power: Power,
+}
+
+/// Parses tokens as it takes them, so they can come straight from a `Scanner`.
+pub struct Parser<'a> {
+    tokens: Box<dyn Iterator<Item = Token<'a>> + 'a>,
+    current: Option<Token<'a>>,
+    // The line of the last token moved past
+    previous_line: Option<usize>,
+    // How deeply nested the expression being parsed is
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: impl IntoIterator<Item = Token<'a>> + 'a) -> Self {
+        let mut tokens = Box::new(tokens.into_iter());
+        let current = tokens.next();
+
         Parser {
-            tokens: tokens,
-            current: 0
+            tokens,
+            current,
+            previous_line: None,
+            depth: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Expr, ParserError> {
-        self.expression() 
+    /// Parses a whole program: a list of declarations ending at EOF.
+    /// After an error the parser skips ahead to the next statement and keeps
+    /// going, so every error in the program is returned, not just the first.
+    pub fn parse(&mut self) -> Result<Vec<Stmt<'a>>, Vec<ParserError<'a>>> {
+        self.parse_with_lines().map(|statements| statements.into_iter().map(|(s, _)| s).collect())
     }
 
-    fn expression(&mut self) -> Result<Expr, ParserError> {
-        self.equality()
-    }
-    
-    fn equality(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.comparison()?;
+    /// Like `parse`, but pairs each statement with the lines its first and last tokens are on.
+    pub fn parse_with_lines(&mut self) -> Result<Vec<(Stmt<'a>, RangeInclusive<usize>)>, Vec<ParserError<'a>>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
 
-        loop {
-            match self.current() {
-                Some(t) if t.token_type == BANG_EQUAL || t.token_type == EQUAL_EQUAL => {
-                    self.advance();
-                    let right = self.comparison()?;
-                    expr = Expr::Binary(BinaryExpr::new(expr, t, right));
+        while !self.is_at_end() {
+            let first = self.current().map_or(0, |t| t.line);
+
+            // An error may have left us partway into an expression
+            self.depth = 0;
+
+            match self.declaration() {
+                Ok(s) => {
+                    let last = self.previous_line.unwrap_or(first);
+                    statements.push((s, first..=last));
                 },
-                None => { return Err(ParserError::new(None)) },
-                _ => { break; }
+                Err(e) => {
+                    errors.push(e);
+                    self.synchronize();
+                }
             }
         }
 
-        Ok(expr)
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
     }
 
-    fn comparison(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.addition()?;
+    /// Parses the tokens as a single expression with nothing after it.
+    /// The REPL uses this to evaluate input like `1 + 2` that has no trailing `;`.
+    pub fn parse_expression(&mut self) -> Result<Expr<'a>, ParserError<'a>> {
+        let expr = self.expression()?;
 
-        loop {
-            match self.current() {
-                Some(t) if t.token_type == GREATER_EQUAL ||
-                            t.token_type == GREATER ||
-                            t.token_type == LESS_EQUAL ||
-                            t.token_type == LESS => {
-                    self.advance();
-                    let right = self.addition()?;
-                    expr = Expr::Binary(BinaryExpr::new(expr, t, right));
-                },
-                None => { return Err(ParserError::new(None)); },
-                _ => { break; }
+        if self.is_at_end() {
+            Ok(expr)
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
+        match self.current() {
+            Some(Token { token_type: VAR, .. }) => {
+                self.advance();
+                self.var_declaration()
+            },
+            Some(_) => self.statement(),
+            None => Err(ParserError::new(None)),
+        }
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
+        let name = self.consume(IDENTIFIER)?;
+
+        // Leave commas out, so `var a = 1, b = 2;` isn't taken to assign to `b`
+        let initializer = match self.advance_if(&[EQUAL]) {
+            Some(_) => Some(self.parse_power(Power::Assignment)?),
+            None => None,
+        };
+
+        self.consume(SEMICOLON)?;
+
+        Ok(Stmt::Var(VarStmt::new(name, initializer)))
+    }
+
+    fn statement(&mut self) -> Result<Stmt<'a>, ParserError<'a>> {
+        match self.advance_if(&[PRINT]) {
+            Some(keyword) => {
+                let value = self.expression()?;
+                self.consume(SEMICOLON)?;
+                Ok(Stmt::Print(PrintStmt::new(keyword, value)))
+            },
+            None => {
+                let line = self.current().map_or(0, |t| t.line);
+                let expr = self.expression()?;
+                self.consume(SEMICOLON)?;
+                Ok(Stmt::Expression(ExpressionStmt::new(expr, line)))
             }
         }
+    }
+
+    fn expression(&mut self) -> Result<Expr<'a>, ParserError<'a>> {
+        self.parse_power(Power::Comma)
+    }
+
+    /// Parses an expression whose operators all bind at least as tightly as
+    /// `power`. The rule for each token says how to parse an expression that
+    /// starts with it, and how to go on when it comes after one.
+    fn parse_power(&mut self, power: Power) -> Result<Expr<'a>, ParserError<'a>> {
+        let depth = self.depth;
+
+        let (token, prefix) = self.advance_with(|t| Parser::rule(&t.token_type).prefix).ok_or_else(|| self.unexpected())?;
+        let mut expr = prefix(self, token)?;
+
+        let infix = |t: &Token<'a>| {
+            let rule = Parser::rule(&t.token_type);
+            rule.infix.filter(|_| rule.power >= power)
+        };
+
+        while let Some((token, infix)) = self.advance_with(infix) {
+            // Each operator in a chain nests what came before it one level deeper
+            self.nest(&token)?;
+            expr = infix(self, expr, token)?;
+        }
 
+        self.depth = depth;
         Ok(expr)
     }
 
-    fn addition(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.multiplication()?;
+    /// How to parse expressions with `token_type` in them.
+    fn rule(token_type: &TokenType) -> Rule<'a> {
+        let (prefix, infix, power): (Option<Prefix<'a>>, Option<Infix<'a>>, Power) = match token_type {
+            LEFT_PAREN => (Some(Parser::grouping), None, Power::None),
+            MINUS => (Some(Parser::unary), Some(Parser::binary), Power::Binary(token_type.precedence())),
+            BANG | TILDE => (Some(Parser::unary), None, Power::None),
+            t if t.precedence() > 0 => (None, Some(Parser::binary), Power::Binary(t.precedence())),
+            EQUAL => (None, Some(Parser::assignment), Power::Assignment),
+            QUESTION => (None, Some(Parser::conditional), Power::Conditional),
+            COMMA => (None, Some(Parser::comma), Power::Comma),
+            FALSE | TRUE | NIL | NUMBER | STRING | INTERPOLATION(_) => (Some(Parser::literal), None, Power::None),
+            IDENTIFIER => (Some(Parser::variable), None, Power::None),
+            _ => (None, None, Power::None),
+        };
 
-        loop {
-            match self.current() {
-                Some(t) if t.token_type == MINUS || t.token_type == PLUS => {
-                    self.advance();
-                    let right = self.multiplication()?;
-                    expr = Expr::Binary(BinaryExpr::new(expr, t, right));
+        Rule { prefix, infix, power }
+    }
+
+    fn grouping(&mut self, paren: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        self.nest(&paren)?;
+        let expr = self.expression()?;
+        self.depth -= 1;
+
+        self.consume(RIGHT_PAREN)?;
+
+        Ok(Expr::Grouping(GroupingExpr::new(expr)))
+    }
+
+    fn unary(&mut self, operator: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        self.nest(&operator)?;
+        // Only `**` binds tighter than a unary operator, so `-a ** b` is `-(a ** b)`
+        let right = self.parse_power(Power::Binary(STAR_STAR.precedence()))?;
+        self.depth -= 1;
+
+        Ok(Expr::Unary(UnaryExpr::new(operator, right)))
+    }
+
+    fn literal(&mut self, token: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        let literal = match token.token_type {
+            FALSE => LiteralExpr::Bool(false),
+            TRUE => LiteralExpr::Bool(true),
+            NIL => LiteralExpr::Nil,
+            NUMBER => LiteralExpr::Number(token.number(), token.lexeme().to_string()),
+            STRING => LiteralExpr::String(token.string().into_owned()),
+            INTERPOLATION(parts) => return self.interpolation(parts, token.line),
+            _ => unreachable!("only literals are parsed as literals"),
+        };
+
+        Ok(Expr::Literal(literal))
+    }
+
+    fn variable(&mut self, name: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        Ok(Expr::Variable(VariableExpr::new(name)))
+    }
+
+    fn binary(&mut self, left: Expr<'a>, operator: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        // The right operand binds one level tighter, so `a - b - c` is `(a - b) - c`,
+        // unless the operator groups from the right
+        let precedence = operator.token_type.precedence();
+        let power = if operator.token_type.is_right_associative() { precedence } else { precedence + 1 };
+        let right = self.parse_power(Power::Binary(power))?;
+
+        Ok(Expr::Binary(BinaryExpr::new(left, operator, right)))
+    }
+
+    fn conditional(&mut self, condition: Expr<'a>, question: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        // Anything goes between `?` and `:`, as in C
+        let then_branch = self.expression()?;
+        self.consume(COLON)?;
+
+        // As in C++ and JavaScript (not C, where it's an error), the else branch
+        // may assign, so `a ? b : c = d` assigns to `c`.
+        // Parsing it this loosely also makes `a ? b : c ? d : e` nest to the right.
+        let else_branch = self.parse_power(Power::Assignment)?;
+
+        Ok(Expr::Conditional(ConditionalExpr::new(condition, question, then_branch, else_branch)))
+    }
+
+    fn comma(&mut self, left: Expr<'a>, _comma: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        let right = self.parse_power(Power::Assignment)?;
+
+        Ok(Expr::Comma(CommaExpr::new(left, right)))
+    }
+
+    fn assignment(&mut self, target: Expr<'a>, equals: Token<'a>) -> Result<Expr<'a>, ParserError<'a>> {
+        // Parsing the value at the same power makes `a = b = c` assign `c` to `b` first
+        let value = self.parse_power(Power::Assignment)?;
+
+        match target {
+            Expr::Variable(v) => Ok(Expr::Assign(AssignExpr::new(v.name, value))),
+            // Only variables can be assigned to
+            _ => Err(ParserError::new(Some(equals))),
+        }
+    }
+
+    /// Parses the code in each `${...}` of an interpolated string as an expression.
+    fn interpolation(&mut self, parts: Vec<StringPart<'a>>, line: usize) -> Result<Expr<'a>, ParserError<'a>> {
+        let mut interpolated = Vec::new();
+
+        for part in parts {
+            match part {
+                StringPart::Text(t) => interpolated.push(InterpolationPart::Text(t)),
+                StringPart::Code(tokens) => {
+                    // What's spliced in is nested inside the expression the string is in
+                    let mut parser = Parser::new(tokens);
+                    parser.depth = self.depth;
+
+                    if let Some(first) = parser.current.clone() {
+                        parser.nest(&first)?;
+                    }
+
+                    interpolated.push(InterpolationPart::Expr(parser.parse_expression()?));
                 },
-                None => { return Err(ParserError::new(None)); },
-                _ => { break; }
             }
         }
 
-        Ok(expr)
+        Ok(Expr::Interpolation(InterpolationExpr::new(interpolated, line)))
     }
 
-    fn multiplication(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.unary()?;
+    /// Discards tokens until we are probably at the start of the next statement.
+    fn synchronize(&mut self) {
+        // Always move past the offending token, otherwise an error on a
+        // statement keyword would have us parse the same token forever
+        let mut skipped = self.advance();
+
+        while !self.is_at_end() {
+            if let Some(Token { token_type: SEMICOLON, .. }) = skipped {
+                return;
+            }
 
-        loop {
             match self.current() {
-                Some(t) if t.token_type == SLASH || t.token_type == STAR => {
-                    self.advance();
-                    let right = self.unary()?;
-                    expr = Expr::Binary(BinaryExpr::new(expr, t, right));
-                },
-                None => { return Err(ParserError::new(None)); },
-                _ => { break; }
+                Some(Token { token_type: CLASS | FUN | VAR | FOR | IF | WHILE | PRINT | RETURN, .. }) => return,
+                _ => skipped = self.advance(),
             }
         }
-
-        Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, ParserError> {
-        match self.current() {
-            Some(t) if t.token_type == BANG || t.token_type == MINUS => {
-                self.advance();
-                let right = self.unary()?;
-                Ok(Expr::Unary(UnaryExpr::new(t, right)))
-            },
-            Some(_) => self.primary(),
-            None => Err(ParserError::new(None)),
+    /// Goes one level deeper into an expression at `token`, unless that's too deep.
+    fn nest(&mut self, token: &Token<'a>) -> Result<(), ParserError<'a>> {
+        if self.depth == MAX_NESTING {
+            return Err(ParserError::too_deep(token.clone()));
         }
+
+        self.depth += 1;
+        Ok(())
     }
 
-    fn primary(&mut self) -> Result<Expr, ParserError> {        
-        let res = match self.current() {
-            Some(Token { token_type: FALSE, .. }) => {
-                self.advance();
-                Ok(Expr::Literal(LiteralExpr::Bool(false)))
-            },
-            Some(Token { token_type: TRUE, ..}) => {
-                self.advance();
-                Ok(Expr::Literal(LiteralExpr::Bool(true)))
-            },
-            Some(Token { token_type: NIL, ..}) => {
-                self.advance();
-                Ok(Expr::Literal(LiteralExpr::Nil))
-            },
-            Some(Token { token_type: NUMBER(n), ..}) => {
-                self.advance();
-                Ok(Expr::Literal(LiteralExpr::Number(n)))
-            },
-            Some(Token { token_type: STRING(s), ..}) => {
-                self.advance();
-                Ok(Expr::Literal(LiteralExpr::String(s)))
-            },
-            Some(Token { token_type: LEFT_PAREN, ..}) => {
-                self.advance();
-                let expr = self.expression()?;
+    /// Moves past the current token and hands it over. Once the tokens run
+    /// out there is nothing to move past.
+    fn advance(&mut self) -> Option<Token<'a>> {
+        let token = self.current.take()?;
 
-                match self.current() {
-                    Some(Token { token_type: RIGHT_PAREN, ..}) => { self.advance(); Ok(Expr::Grouping(GroupingExpr::new(expr)))} ,
-                    unexpected @ _ => Err(ParserError::new(unexpected)),
-                }
-            },
-            unexpected @ Some(_) => Err(ParserError::new(unexpected)),
-            None => Err(ParserError::new(None)),
-        };
+        self.current = self.tokens.next();
+        self.previous_line = Some(token.line);
 
-        res
+        Some(token)
     }
 
-    fn advance(&mut self) {
-        // this prevents us from incrementing the current counter
-        // when we reach the end
-        if self.current().is_some() {
-            self.current += 1;
-        }
+    /// Advances past the current token if it's one of `types`, handing it over.
+    fn advance_if(&mut self, types: &[TokenType]) -> Option<Token<'a>> {
+        self.advance_with(|t| types.contains(&t.token_type).then_some(())).map(|(t, _)| t)
+    }
+
+    /// Advances past the current token if `f` finds something in it, handing both over.
+    fn advance_with<T>(&mut self, f: impl Fn(&Token<'a>) -> Option<T>) -> Option<(Token<'a>, T)> {
+        let found = self.current().and_then(f)?;
+
+        self.advance().map(|t| (t, found))
+    }
+
+    /// Advances past the current token if it has the expected type,
+    /// otherwise reports it as unexpected.
+    fn consume(&mut self, expected: TokenType) -> Result<Token<'a>, ParserError<'a>> {
+        self.advance_if(&[expected]).ok_or_else(|| self.unexpected())
     }
 
-    fn current(&self) -> Option<Token> {
-        self.tokens.get(self.current).cloned()
+    /// An error at the current token.
+    fn unexpected(&self) -> ParserError<'a> {
+        ParserError::new(self.current.clone())
     }
 
-    fn next(&self) -> Option<Token> {
-        self.tokens.get(self.current + 1).cloned()
+    fn is_at_end(&self) -> bool {
+        matches!(self.current(), None | Some(Token { token_type: EOF, .. }))
     }
 
-    fn previous(&self) -> Option<Token> {
-        self.tokens.get(self.current - 1).cloned()
+    fn current(&self) -> Option<&Token<'a>> {
+        self.current.as_ref()
     }
-}
\ No newline at end of file
+}
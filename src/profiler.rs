@@ -0,0 +1,213 @@
+//! # Lox Profiler
+//!
+//! An instrumenting profiler for `rlox run --profile`. It attaches to the
+//! interpreter as a `Hook` and looks at the clock before every statement. The
+//! time between two statements is charged to the first of them, to the
+//! function it ran in and to the call stack it ran under.
+//!
+//! When the program ends the profiler prints the busiest functions and lines to
+//! stderr, leaving stdout to the program, and writes the time spent on each
+//! line under each call stack as folded stacks, the format flamegraph tools
+//! read. The line is the innermost frame of every stack.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use crate::interpreter::{Hook, Interpreter, RuntimeError};
+use crate::lox::{Lox, EX_DATAERR, EX_IOERR, EX_SOFTWARE};
+use crate::loxerror::LoxError;
+use crate::stmt::Stmt;
+
+/// Where the folded stacks are written unless the caller says otherwise.
+pub const FOLDED_PATH: &str = "rlox.folded";
+
+// How many functions and lines the report lists
+const TOP: usize = 10;
+
+/// Runs `source` under the profiler, writes its folded stacks to `folded_path`
+/// and returns the exit code the process should use.
+pub fn run(source: &str, folded_path: &str) -> i32 {
+    let statements = match Lox::parse(source) {
+        Some(statements) => statements,
+        None => return EX_DATAERR,
+    };
+
+    let profile = Rc::new(RefCell::new(Profile::default()));
+    let mut interpreter = Interpreter::new();
+    interpreter.set_hook(Box::new(ProfilerHook(Rc::clone(&profile))));
+
+    let mut code = match interpreter.interpret(&statements) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", LoxError::from(e));
+            EX_SOFTWARE
+        }
+    };
+
+    let mut profile = profile.borrow_mut();
+    profile.finish();
+
+    eprint!("{}", profile.report(TOP));
+
+    match fs::write(folded_path, profile.folded()) {
+        Ok(_) => eprintln!("\nFolded stacks written to {}", folded_path),
+        Err(e) => {
+            eprintln!("Could not write {}: {}", folded_path, e);
+            code = EX_IOERR;
+        }
+    }
+
+    code
+}
+
+/// What the profiler knows about one function.
+#[derive(Default)]
+pub struct FunctionProfile {
+    pub calls: usize,
+    // Including the functions it called
+    pub inclusive: Duration,
+    // Only while it was the innermost frame
+    pub exclusive: Duration,
+}
+
+/// What the profiler knows about one line.
+#[derive(Default)]
+pub struct LineProfile {
+    // How many statements on the line ran
+    pub hits: usize,
+    pub time: Duration,
+}
+
+/// Everything measured while a program ran.
+#[derive(Default)]
+pub struct Profile {
+    pub functions: HashMap<String, FunctionProfile>,
+    pub lines: HashMap<usize, LineProfile>,
+    // Time spent in each call stack, outermost frame first, down to the line
+    pub stacks: HashMap<Vec<String>, Duration>,
+    // The call stack and line of the statement running now, and when it started
+    stack: Vec<String>,
+    line: Option<usize>,
+    started: Option<Instant>,
+}
+
+impl Profile {
+    /// Notes that a statement on `line` is about to run under `frames`.
+    fn enter_statement(&mut self, frames: Vec<String>, line: usize) {
+        self.charge(Instant::now());
+
+        // Frames that weren't on the stack last time are new calls. A function
+        // that returns and is called again between two statements at the same
+        // depth looks like it never left, so those calls are missed.
+        let common = self.stack.iter().zip(&frames).take_while(|(a, b)| a == b).count();
+
+        for name in &frames[common..] {
+            self.functions.entry(name.clone()).or_default().calls += 1;
+        }
+
+        self.lines.entry(line).or_default().hits += 1;
+        self.stack = frames;
+        self.line = Some(line);
+    }
+
+    /// Charges the time since the last statement started to it, and stops the clock.
+    fn finish(&mut self) {
+        self.charge(Instant::now());
+        self.started = None;
+    }
+
+    fn charge(&mut self, now: Instant) {
+        let elapsed = match self.started {
+            Some(started) => now - started,
+            None => Duration::ZERO,
+        };
+
+        if let Some(line) = self.line {
+            self.lines.entry(line).or_default().time += elapsed;
+        }
+
+        if let Some(innermost) = self.stack.last() {
+            self.functions.entry(innermost.clone()).or_default().exclusive += elapsed;
+        }
+
+        // A recursive function only counts once towards its inclusive time
+        let mut seen: Vec<&String> = Vec::new();
+
+        for name in &self.stack {
+            if !seen.contains(&name) {
+                self.functions.entry(name.clone()).or_default().inclusive += elapsed;
+                seen.push(name);
+            }
+        }
+
+        if let Some(line) = self.line {
+            let mut stack = self.stack.clone();
+            stack.push(format!("line {}", line));
+
+            *self.stacks.entry(stack).or_default() += elapsed;
+        }
+    }
+
+    /// The `top` functions by exclusive time and the `top` lines by time.
+    pub fn report(&self, top: usize) -> String {
+        let total: Duration = self.stacks.values().sum();
+        let percent = |d: Duration| if total.is_zero() { 0.0 } else { 100.0 * d.as_secs_f64() / total.as_secs_f64() };
+
+        let mut functions: Vec<(&String, &FunctionProfile)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+
+        let mut lines: Vec<(&usize, &LineProfile)> = self.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+
+        let mut report = format!("\nProfile: {:?} in total\n\n", total);
+
+        report.push_str(&format!("{:>8} {:>12} {:>7} {:>12} {:>7}  function\n", "calls", "exclusive", "%", "inclusive", "%"));
+
+        for (name, f) in functions.into_iter().take(top) {
+            report.push_str(&format!(
+                "{:>8} {:>12} {:>6.1}% {:>12} {:>6.1}%  {}\n",
+                f.calls,
+                format!("{:?}", f.exclusive), percent(f.exclusive),
+                format!("{:?}", f.inclusive), percent(f.inclusive),
+                name,
+            ));
+        }
+
+        report.push_str(&format!("\n{:>8} {:>12} {:>7}  line\n", "hits", "time", "%"));
+
+        for (line, l) in lines.into_iter().take(top) {
+            report.push_str(&format!("{:>8} {:>12} {:>6.1}%  {}\n", l.hits, format!("{:?}", l.time), percent(l.time), line));
+        }
+
+        report
+    }
+
+    /// One `outer;inner;line N microseconds` line per call stack and line,
+    /// sorted so the output doesn't change between runs of the same program.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<String> = self.stacks.iter()
+            .map(|(stack, time)| format!("{} {}\n", stack.join(";"), time.as_micros()))
+            .collect();
+
+        stacks.sort();
+        stacks.concat()
+    }
+}
+
+struct ProfilerHook(Rc<RefCell<Profile>>);
+
+impl Hook for ProfilerHook {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, statement: &Stmt) -> Result<(), RuntimeError> {
+        let frames = interpreter.frames.iter().map(|f| f.name.clone()).collect();
+
+        let mut profile = self.0.borrow_mut();
+        profile.enter_statement(frames, statement.line());
+
+        // Start the clock last so the profiler's own bookkeeping isn't charged to the program
+        profile.started = Some(Instant::now());
+
+        Ok(())
+    }
+}
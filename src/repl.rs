@@ -0,0 +1,229 @@
+//! # REPL meta-commands
+//! 
+//! Lines typed at the prompt that start with `:` control the session
+//! instead of being run as Lox code.
+
+use std::env;
+use std::io::{self, stdin, stdout, IsTerminal, Write};
+use std::path::PathBuf;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use unicode_xid::UnicodeXID;
+use crate::scanner::KEY_WORDS;
+
+pub const HELP: &str = "\
+:help           Show this message
+:quit           Leave the REPL
+:load <file>    Run a script in the current session
+:reset          Forget everything defined so far
+:env            List defined globals and their values
+:tokens <src>   Show the tokens the scanner produces for <src>
+:ast <src>      Show the syntax tree the parser produces for <src>
+:time <expr>    Evaluate <expr> and report how long it took";
+
+const COMMANDS: &[&str] = &[":help", ":quit", ":load", ":reset", ":env", ":tokens", ":ast", ":time"];
+
+pub enum Command<'a> {
+    Help,
+    Quit,
+    Load(&'a str),
+    Reset,
+    Env,
+    Tokens(&'a str),
+    Ast(&'a str),
+    Time(&'a str),
+}
+
+impl<'a> Command<'a> {
+    /// Returns `None` when the line is ordinary Lox code, and an error message
+    /// when it looks like a command but isn't one we understand.
+    pub fn parse(line: &'a str) -> Option<Result<Command<'a>, String>> {
+        let line = line.trim();
+
+        if !line.starts_with(':') {
+            return None;
+        }
+
+        let (name, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[1..i], line[i..].trim()),
+            None => (&line[1..], ""),
+        };
+
+        let command = match (name, arg) {
+            ("help", "") => Ok(Command::Help),
+            ("quit", "") => Ok(Command::Quit),
+            ("reset", "") => Ok(Command::Reset),
+            ("env", "") => Ok(Command::Env),
+            ("help", _) | ("quit", _) | ("reset", _) | ("env", _) => Err(format!(":{} takes no arguments", name)),
+            ("load", "") | ("tokens", "") | ("ast", "") | ("time", "") => Err(format!(":{} needs an argument", name)),
+            ("load", path) => Ok(Command::Load(path)),
+            ("tokens", src) => Ok(Command::Tokens(src)),
+            ("ast", src) => Ok(Command::Ast(src)),
+            ("time", src) => Ok(Command::Time(src)),
+            _ => Err(format!("Unknown command :{}. Type :help for a list of commands.", name)),
+        };
+
+        Some(command)
+    }
+}
+
+/// Where the REPL reads its lines from. When stdin is a terminal we get line
+/// editing, history and tab completion; otherwise (e.g. input piped in from a
+/// file) lines are read as-is.
+pub enum Input {
+    Plain,
+    Terminal {
+        editor: Box<Editor<LoxHelper, FileHistory>>,
+        history: Option<PathBuf>,
+    },
+}
+
+impl Input {
+    pub fn new() -> Self {
+        if !stdin().is_terminal() {
+            return Input::Plain;
+        }
+
+        let mut editor = match Editor::new() {
+            Ok(e) => e,
+            Err(_) => return Input::Plain,
+        };
+
+        editor.set_helper(Some(LoxHelper::default()));
+
+        let history = history_path();
+
+        if let Some(path) = &history {
+            // A missing history file just means this is the first session
+            let _ = editor.load_history(path);
+        }
+
+        Input::Terminal { editor: Box::new(editor), history }
+    }
+
+    /// Reads the next line, returning `None` once the input is exhausted.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        match self {
+            Input::Plain => {
+                print!("{}", prompt);
+                stdout().flush()?;
+
+                let mut line = String::new();
+
+                match stdin().read_line(&mut line)? {
+                    0 => Ok(None),
+                    _ => Ok(Some(line)),
+                }
+            },
+            Input::Terminal { editor, history } => {
+                loop {
+                    match editor.readline(prompt) {
+                        Ok(line) => {
+                            if !line.trim().is_empty() {
+                                let _ = editor.add_history_entry(line.as_str());
+
+                                if let Some(path) = history {
+                                    let _ = editor.append_history(path);
+                                }
+                            }
+
+                            return Ok(Some(line));
+                        },
+                        // Ctrl-C throws away the current line, like in a shell
+                        Err(ReadlineError::Interrupted) => continue,
+                        Err(ReadlineError::Eof) => return Ok(None),
+                        Err(ReadlineError::Io(e)) => return Err(e),
+                        Err(e) => return Err(io::Error::other(e)),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lets tab completion offer the globals currently defined in the session.
+    pub fn set_globals(&mut self, globals: Vec<String>) {
+        if let Input::Terminal { editor, .. } = self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.set_globals(globals);
+            }
+        }
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::new()
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".rlox_history"))
+}
+
+/// Completes keywords, globals and meta-commands for the line editor.
+#[derive(Default)]
+pub struct LoxHelper {
+    globals: Vec<String>,
+}
+
+impl LoxHelper {
+    /// Replaces the globals offered alongside the keywords.
+    pub fn set_globals(&mut self, globals: Vec<String>) {
+        self.globals = globals;
+    }
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        // Words are identifiers as the scanner sees them, or commands
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !(c.is_xid_continue() || *c == '_' || *c == ':'))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+
+        let word = &line[start..pos];
+
+        if word.is_empty() {
+            return Ok((start, Vec::new()));
+        }
+
+        let mut candidates: Vec<String> = if word.starts_with(':') {
+            COMMANDS.iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| String::from(*c))
+                .collect()
+        } else {
+            KEY_WORDS.iter()
+                .map(|(k, _)| *k)
+                .chain(self.globals.iter().map(String::as_str))
+                .filter(|c| c.starts_with(word))
+                .map(String::from)
+                .collect()
+        };
+
+        candidates.sort();
+        candidates.dedup();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
@@ -1,73 +1,218 @@
 //! # Lox Scanner
-//! 
+//!
+//! Identifiers follow Unicode's XID rules, so `naïve` and `π` are names, and
+//! any Unicode whitespace separates tokens. Only `\n` ends a line, though, so
+//! line numbers agree with editors. Columns count characters, not bytes.
 
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::borrow::Cow;
+use std::ops::Range;
+use unicode_xid::UnicodeXID;
 use crate::loxerror;
-use crate::token::{Token, TokenType};
+use crate::token::{Token, TokenType, StringPart, Comment};
+
+// The byte order mark some editors put at the start of UTF-8 files
+const BOM: char = '\u{FEFF}';
+
+// How many interpolated strings may nest inside each other
+const MAX_INTERPOLATION_DEPTH: usize = 16;
+
+/// Reserved words and the token each one scans to.
+pub const KEY_WORDS: &[(&str, TokenType<'static>)] = &[
+    ("and", TokenType::AND),
+    ("class", TokenType::CLASS),
+    ("else", TokenType::ELSE),
+    ("false", TokenType::FALSE),
+    ("for", TokenType::FOR),
+    ("fun", TokenType::FUN),
+    ("if", TokenType::IF),
+    ("nil", TokenType::NIL),
+    ("or", TokenType::OR),
+    ("print", TokenType::PRINT),
+    ("return", TokenType::RETURN),
+    ("super", TokenType::SUPER),
+    ("this", TokenType::THIS),
+    ("true", TokenType::TRUE),
+    ("var", TokenType::VAR),
+    ("while", TokenType::WHILE),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ScanError {
+    pub fn new(line: usize, column: usize, message: &str) -> Self {
+        ScanError {
+            line,
+            column,
+            message: String::from(message),
+        }
+    }
+}
 
-pub struct Scanner {
-    chars: Vec<char>,
-    tokens: Vec<Token>,
+/// Scans tokens on demand. As an iterator it yields each token, or each
+/// error, as it comes to it, ending with EOF. The tokens borrow their text
+/// from the source the scanner was given, so scanning doesn't copy any of it.
+pub struct Scanner<'a> {
+    source: &'a str,
+    // Tokens and errors scanned but not handed out yet
+    tokens: VecDeque<Token<'a>>,
+    // The range of bytes each token in `tokens` was scanned from
+    spans: VecDeque<Range<usize>>,
+    errors: VecDeque<ScanError>,
+    // Where the token being scanned and the current character are, in bytes
     start: usize,
     current: usize,
+    // The same, counted in characters
+    start_position: usize,
+    position: usize,
     line: usize,
-    key_words: HashMap<String, TokenType>,
+    // The position the line `current` is on starts at
+    line_start: usize,
+    // Where the token being scanned starts
+    start_line: usize,
+    start_column: usize,
+    // How many interpolated strings deep we are
+    interpolation_depth: usize,
+    finished: bool,
+    key_words: HashMap<String, TokenType<'a>>,
+    comments: Vec<Comment>,
 }
 
-impl Scanner {
+impl<'a> Scanner<'a> {
+    #[allow(clippy::unnecessary_map_or)]
     fn is_digit(c: Option<char>) -> bool {
         c.map_or(false, |e| e.is_ascii_digit())
     }
 
     fn is_alphabetic(c: Option<char>) -> bool {
-        c.map_or(false, |e| e.is_ascii_alphabetic() || e == '_')
+        c.is_some_and(|e| e == '_' || e.is_xid_start())
     }
 
     fn is_alphanumeric(c: Option<char>) -> bool {
-        Scanner::is_alphabetic(c) || Scanner::is_digit(c)
-    }
-
-    pub fn new(source: &str) -> Self {
-        let key_words: HashMap<String, TokenType> = [
-            (String::from("and"), TokenType::AND),
-            (String::from("class"), TokenType::CLASS),
-            (String::from("else"), TokenType::ELSE),
-            (String::from("false"), TokenType::FALSE),
-            (String::from("for"), TokenType::FOR),
-            (String::from("fun"), TokenType::FUN),
-            (String::from("if"), TokenType::IF),
-            (String::from("nil"), TokenType::NIL),
-            (String::from("or"), TokenType::OR),
-            (String::from("print"), TokenType::PRINT),
-            (String::from("return"), TokenType::RETURN),
-            (String::from("super"), TokenType::SUPER),
-            (String::from("this"), TokenType::THIS),
-            (String::from("true"), TokenType::TRUE),
-            (String::from("var"), TokenType::VAR),
-            (String::from("while"), TokenType::WHILE),
-        ].iter().cloned().collect();
+        c.is_some_and(|e| e.is_xid_continue())
+    }
+
+    /// Whether `c` separates tokens without ending a line.
+    pub fn is_whitespace(c: char) -> bool {
+        c != '\n' && c.is_whitespace()
+    }
+
+    #[allow(clippy::redundant_field_names)]
+    pub fn new(source: &'a str) -> Self {
+        let key_words: HashMap<String, TokenType> = KEY_WORDS
+            .iter()
+            .map(|(k, t)| (String::from(*k), t.clone()))
+            .collect();
 
         Self {
-            chars: source.chars().collect(),
-            tokens: Vec::new(),
+            source,
+            tokens: VecDeque::new(),
+            spans: VecDeque::new(),
+            errors: VecDeque::new(),
             start: 0,
             current: 0,
+            start_position: 0,
+            position: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            interpolation_depth: 0,
+            finished: false,
             key_words: key_words,
+            comments: Vec::new(),
         }
     }
 
-    pub fn scan_tokens(mut self) -> Vec<Token> {
-        while self.current() != None {
-            self.start = self.current;
-            
+    /// The tokens, scanned as they're asked for, reporting errors along the way.
+    /// The parser can take them straight from here.
+    pub fn reporting(self) -> impl Iterator<Item = Token<'a>> {
+        self.filter_map(reported)
+    }
+
+    /// Scans every token, reporting errors as it goes.
+    pub fn scan_tokens(self) -> Vec<Token<'a>> {
+        self.scan_tokens_and_comments().0
+    }
+
+    /// Like `scan_tokens`, but also hands back the comments the scanner skipped over.
+    pub fn scan_tokens_and_comments(mut self) -> (Vec<Token<'a>>, Vec<Comment>) {
+        let tokens = self.by_ref().filter_map(reported).collect();
+
+        (tokens, self.comments)
+    }
+
+    /// Pairs each token with the range of bytes of the source
+    /// it was scanned from. Errors are left out without being reported.
+    pub fn scan_tokens_with_spans(mut self) -> Vec<(Token<'a>, Range<usize>)> {
+        let mut tokens = Vec::new();
+
+        while let Some(result) = self.next_with_span() {
+            if let Ok(token) = result {
+                tokens.push(token);
+            }
+        }
+
+        tokens
+    }
+
+    /// Scans without printing errors or flagging them globally, handing them
+    /// back instead. For tools like the language server that show errors themselves.
+    pub fn scan_tokens_quietly(self) -> (Vec<Token<'a>>, Vec<ScanError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+
+        for result in self {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        (tokens, errors)
+    }
+
+    /// Scans as far as the next token or error, with the characters the token
+    /// was scanned from. Errors come before the token they were found in.
+    fn next_with_span(&mut self) -> Option<Result<(Token<'a>, Range<usize>), ScanError>> {
+        while self.tokens.is_empty() && self.errors.is_empty() && self.current().is_some() {
+            self.start_token();
             self.scan_token();
         }
 
-        self.tokens.push(Token::new(TokenType::EOF, String::from(""), self.line));
+        if let Some(error) = self.errors.pop_front() {
+            return Some(Err(error));
+        }
+
+        if let (Some(token), Some(span)) = (self.tokens.pop_front(), self.spans.pop_front()) {
+            return Some(Ok((token, span)));
+        }
+
+        if self.finished {
+            return None;
+        }
+
+        self.finished = true;
 
-        self.tokens
+        let column = self.position - self.line_start + 1;
+        let end = self.source.len();
+        let token = Token::new(TokenType::EOF, &self.source[end..], self.line, column);
+
+        Some(Ok((token, end..end)))
+    }
+
+    /// Marks the current character as the start of the next token.
+    fn start_token(&mut self) {
+        self.start = self.current;
+        self.start_position = self.position;
+        self.start_line = self.line;
+        self.start_column = self.start_position - self.line_start + 1;
     }
 
     fn scan_token(&mut self) {
@@ -82,7 +227,23 @@ impl Scanner {
             Some('-') => self.add_token(TokenType::MINUS),
             Some('+') => self.add_token(TokenType::PLUS),
             Some(';') => self.add_token(TokenType::SEMICOLON),
-            Some('*') => self.add_token(TokenType::STAR),
+            Some('?') => self.add_token(TokenType::QUESTION),
+            Some(':') => self.add_token(TokenType::COLON),
+            Some('%') => self.add_token(TokenType::PERCENT),
+            Some('&') => self.add_token(TokenType::AMPERSAND),
+            Some('|') => self.add_token(TokenType::PIPE),
+            Some('^') => self.add_token(TokenType::CARET),
+            // `~/` is integer division, since `//` starts a comment. A slash
+            // that starts a comment right after a `~` is still a comment.
+            Some('~') => {
+                let token = if self.peek(1) == Some('/') && !matches!(self.peek(2), Some('/' | '*')) {
+                    self.advance();
+                    TokenType::TILDE_SLASH
+                } else {
+                    TokenType::TILDE
+                };
+                self.add_token(token);
+            },
 
             // Lexemes that could be both single or double characters
             Some('!') => {
@@ -95,11 +256,27 @@ impl Scanner {
                 self.add_token(token);
             },
             Some('<') => {
-                let token = if self.try_advance('=') { TokenType::LESS_EQUAL } else { TokenType::LESS };
+                let token = if self.try_advance('=') {
+                    TokenType::LESS_EQUAL
+                } else if self.try_advance('<') {
+                    TokenType::LESS_LESS
+                } else {
+                    TokenType::LESS
+                };
                 self.add_token(token);
             },
             Some('>') => {
-                let token = if self.try_advance('=') { TokenType::GREATER_EQUAL} else { TokenType::GREATER };
+                let token = if self.try_advance('=') {
+                    TokenType::GREATER_EQUAL
+                } else if self.try_advance('>') {
+                    TokenType::GREATER_GREATER
+                } else {
+                    TokenType::GREATER
+                };
+                self.add_token(token);
+            },
+            Some('*') => {
+                let token = if self.try_advance('*') { TokenType::STAR_STAR } else { TokenType::STAR };
                 self.add_token(token);
             },
 
@@ -107,19 +284,28 @@ impl Scanner {
             Some('/') => {
                 if self.try_advance('/') {
                     // Ah, it's a comment line
-                    while self.next(1) != Some('\n') {
+                    while self.peek(1) != Some('\n') && self.peek(1).is_some() {
                         self.advance();
                     }
+
+                    let text = self.lexeme().trim_end().to_string();
+
+                    self.comments.push(Comment::new(text, self.line));
+                } else if self.try_advance('*') {
+                    self.handle_block_comment();
                 } else {
                     self.add_token(TokenType::SLASH);
                 }
             }
 
             // Ignore non-meaningful whitespace characters
-            Some(' ') | Some('\r') | Some('\t') => {},
+            Some(w) if Scanner::is_whitespace(w) => {},
+
+            // A byte order mark is only allowed before anything else
+            Some(BOM) if self.current == 0 => {},
 
             // When we encounter a new-line character, increment our line count
-            Some('\n') => { self.line += 1; } ,
+            Some('\n') => { self.new_line(self.position + 1); } ,
 
             // strings
             Some('"') => self.handle_string(),
@@ -130,7 +316,9 @@ impl Scanner {
             // identifiers
             Some(i) if Scanner::is_alphabetic(Some(i)) => self.handle_identifier(),
 
-            Some(u) => loxerror::error(self.line, &format!("Unexpected character: {}", u)),
+            // Name characters outside ASCII too, since many of them are invisible
+            Some(u) if u.is_ascii_graphic() => self.error(self.start_column, &format!("Unexpected character: {}", u)),
+            Some(u) => self.error(self.start_column, &format!("Unexpected character: U+{:04X}", u as u32)),
 
             // the method calling `scan_token` checks before hand that we are not at the end
             None => unreachable!(), 
@@ -139,14 +327,31 @@ impl Scanner {
         self.advance();
     }
 
+    /// Moves on to the next line, which starts at `line_start`.
+    fn new_line(&mut self, line_start: usize) {
+        self.line += 1;
+        self.line_start = line_start;
+    }
+
+    fn error(&mut self, column: usize, message: &str) {
+        self.error_at(self.start_line, column, message);
+    }
+
+    /// Reports an error somewhere other than the line the current token started on.
+    fn error_at(&mut self, line: usize, column: usize, message: &str) {
+        self.errors.push_back(ScanError::new(line, column, message));
+    }
+
     fn advance(&mut self) {
-        if self.current().is_some() {
-            self.current += 1;
+        if let Some(c) = self.current() {
+            self.current += c.len_utf8();
+            self.position += 1;
         }
     }
 
+    #[allow(clippy::unnecessary_map_or)]
     fn try_advance(&mut self, expected: char) -> bool {
-        if self.next(1).map_or(false, |e| e == expected) {
+        if self.peek(1).map_or(false, |e| e == expected) {
             self.advance();
 
             true
@@ -156,67 +361,492 @@ impl Scanner {
     }
 
     fn current(&self) -> Option<char> {
-        self.chars.get(self.current).cloned()
+        self.source[self.current..].chars().next()
     }
 
-    fn next(&self, i: usize) -> Option<char> {
-        self.chars.get(self.current + i).cloned()
+    fn peek(&self, i: usize) -> Option<char> {
+        self.source[self.current..].chars().nth(i)
     }
 
-    fn add_token(&mut self, token_type: TokenType) {
-        let lexeme = self.chars[self.start..=self.current].iter().collect();
+    fn add_token(&mut self, token_type: TokenType<'a>) {
+        let token = Token::new(token_type, &self.source[self.start..self.end()], self.start_line, self.start_column);
 
-        self.tokens.push(Token::new(token_type, lexeme, self.line));
+        self.tokens.push_back(token);
+        self.spans.push_back(self.start..self.end());
     }
 
     fn handle_string(&mut self) {
-        // Find the end of the string
-        while self.next(1) != Some('"') && self.next(1) != None {
-            // If we encounter a newline in the middle of the string, just increment the line counter
-            // and keep looking for the end of the string
-            if self.next(1) == Some('\n') { self.line += 1; }
+        let mut parts = Vec::new();
+        let mut text = self.text_start();
+        let mut too_deep = false;
+
+        // Find the end of the string. Escapes are only skipped over on the way,
+        // and each stretch of text between `${...}`s is unescaped once it ends.
+        loop {
+            match self.peek(1) {
+                None => {
+                    self.unescape(&text);
+                    self.error(self.start_column, "Unterminated string");
+                    return;
+                },
+                Some('"') => break,
+                // A backslash ending a line is an error, but the newline still needs counting
+                Some('\\') if !matches!(self.peek(2), None | Some('\n')) => {
+                    self.advance();
+                    self.advance();
+                },
+                Some('$') if self.peek(2) == Some('{') => {
+                    parts.push(StringPart::Text(self.unescape(&text)));
+
+                    self.advance();
+                    self.advance();
+
+                    match self.handle_interpolation() {
+                        Some(code) => parts.push(StringPart::Code(code)),
+                        None => too_deep = true,
+                    }
 
-            self.advance();
+                    text = self.text_start();
+                },
+                Some(c) => {
+                    // If we encounter a newline in the middle of the string, just increment the line counter
+                    // and keep looking for the end of the string
+                    if c == '\n' { self.new_line(self.position + 2); }
+
+                    self.advance();
+                },
+            }
         }
 
-        if self.next(1) == None {
-            loxerror::error(self.line, "Unterminated string");
-            return;
+        let value = self.unescape(&text);
+
+        self.advance();
+
+        // Code nested too deeply has been reported already. As a plain string,
+        // the parser won't find fault with its missing code as well.
+        if parts.is_empty() || too_deep {
+            self.add_token(TokenType::STRING);
+        } else {
+            parts.push(StringPart::Text(value));
+            parts.retain(|p| *p != StringPart::Text(String::new()));
+
+            self.add_token(TokenType::INTERPOLATION(parts));
         }
+    }
+
+    /// Notes that a stretch of string text starts after the current character.
+    fn text_start(&self) -> TextStart {
+        TextStart { start: self.end(), position: self.position + 1, line: self.line, line_start: self.line_start }
+    }
+
+    /// Unescapes the text from `text` up to and including the current
+    /// character, reporting any malformed escape sequences where they are.
+    fn unescape(&mut self, text: &TextStart) -> String {
+        let slice = &self.source[text.start..self.end()];
+        let mut errors = Vec::new();
+
+        let value = unescape(slice, |offset, message| {
+            let (mut line, mut line_start) = (text.line, text.line_start);
 
+            for (i, c) in slice.chars().take(offset).enumerate() {
+                if c == '\n' {
+                    line += 1;
+                    line_start = text.position + i + 1;
+                }
+            }
+
+            errors.push(ScanError::new(line, text.position + offset - line_start + 1, message));
+        });
+
+        self.errors.extend(errors);
+        value.into_owned()
+    }
+
+    /// Scans the code in a `${...}` whose `{` is the current character, and stops on
+    /// the `}` that closes it. The tokens end with an EOF for the parser. Code
+    /// nested too deeply is reported and skipped, and gives no tokens.
+    fn handle_interpolation(&mut self) -> Option<Vec<Token<'a>>> {
+        // Scanning the code moves the start along, but the string still needs it
+        let (start, start_position) = (self.start, self.start_position);
+        let (start_line, start_column) = (self.start_line, self.start_column);
+        let opening = (self.line, self.position - self.line_start);
+        let first = self.tokens.len();
+        let mut braces = 0;
+
+        self.interpolation_depth += 1;
         self.advance();
 
-        let val = self.chars[self.start + 1..=self.current - 1].iter().collect();
+        let too_deep = self.interpolation_depth > MAX_INTERPOLATION_DEPTH;
+
+        if too_deep {
+            self.error_at(opening.0, opening.1, "Interpolated strings nest too deeply");
+            self.skip_interpolation();
+        }
+
+        loop {
+            match self.current() {
+                // Let `handle_string` report the unterminated string
+                None => break,
+                Some('}') if braces == 0 => break,
+                _ => {},
+            }
+
+            let scanned = self.tokens.len();
+
+            self.start_token();
+            self.scan_token();
+
+            match self.tokens.get(scanned).map(|t| &t.token_type) {
+                Some(TokenType::LEFT_BRACE) => braces += 1,
+                Some(TokenType::RIGHT_BRACE) => braces -= 1,
+                _ => {},
+            }
+        }
+
+        let mut tokens: Vec<Token<'a>> = self.tokens.split_off(first).into();
+        self.spans.truncate(first);
+
+        let column = self.position - self.line_start + 1;
+        tokens.push(Token::new(TokenType::EOF, &self.source[self.current..self.current], self.line, column));
+
+        self.interpolation_depth -= 1;
+        self.start = start;
+        self.start_position = start_position;
+        self.start_line = start_line;
+        self.start_column = start_column;
+
+        if too_deep { None } else { Some(tokens) }
+    }
+
+    /// Skips the code in a `${...}` rather than scan it, which would go deeper
+    /// still, and stops on the `}` that closes it. Strings and braces in the
+    /// code are followed just well enough to find that `}`.
+    fn skip_interpolation(&mut self) {
+        // How many braces are open in each level of code we're in
+        let mut braces = vec![0];
+        let mut in_string = false;
+
+        while let Some(c) = self.current() {
+            match (in_string, c) {
+                (false, '}') if braces.last() == Some(&0) => {
+                    braces.pop();
+
+                    if braces.is_empty() { return; }
+
+                    in_string = true;
+                },
+                (false, '}') => *braces.last_mut().unwrap() -= 1,
+                (false, '{') => *braces.last_mut().unwrap() += 1,
+                (_, '"') => in_string = !in_string,
+                (true, '\\') if !matches!(self.peek(1), None | Some('\n')) => self.advance(),
+                (true, '$') if self.peek(1) == Some('{') => {
+                    self.advance();
+                    braces.push(0);
+                    in_string = false;
+                },
+                (_, '\n') => self.new_line(self.position + 1),
+                _ => {},
+            }
+
+            self.advance();
+        }
+    }
+
+    /// Skips a `/* ... */` comment whose `*` is the current character. Block
+    /// comments nest, so each `/*` inside needs a `*/` of its own.
+    fn handle_block_comment(&mut self) {
+        let mut depth = 1;
+
+        while depth > 0 {
+            match (self.peek(1), self.peek(2)) {
+                (None, _) => {
+                    self.error(self.start_column, "Unterminated block comment");
+                    break;
+                },
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    self.advance();
+                    self.advance();
+                },
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    self.advance();
+                    self.advance();
+                },
+                (Some(c), _) => {
+                    if c == '\n' { self.new_line(self.position + 2); }
+
+                    self.advance();
+                },
+            }
+        }
+
+        let text = self.lexeme().to_string();
 
-        self.add_token(TokenType::STRING(val));
+        self.comments.push(Comment::new(text, self.start_line));
     }
 
     fn handle_number(&mut self) {
+        let radix = match (self.current(), self.peek(1)) {
+            (Some('0'), Some('x' | 'X')) => Some((16, "a hexadecimal")),
+            (Some('0'), Some('o' | 'O')) => Some((8, "an octal")),
+            (Some('0'), Some('b' | 'B')) => Some((2, "a binary")),
+            _ => None,
+        };
+
+        if let Some((radix, name)) = radix {
+            self.advance();
+            self.handle_radix_number(radix, name);
+            return;
+        }
+
         // Read digits until you can read no more
-        while Scanner::is_digit(self.next(1)) { self.advance(); }
+        self.skip_digits();
 
-        if self.next(1) == Some('.') && Scanner::is_digit(self.next(2)) {
+        if self.peek(1) == Some('.') && Scanner::is_digit(self.peek(2)) {
             self.advance();
+            self.skip_digits();
+        }
+
+        // An `e` with a letter after it is the start of a name, not an exponent
+        let exponent = matches!(self.peek(1), Some('e' | 'E'))
+            && (self.peek(2) == Some('_') || !Scanner::is_alphabetic(self.peek(2)));
 
-            while Scanner::is_digit(self.next(1)) { self.advance(); }
+        if exponent {
+            self.advance();
+
+            if matches!(self.peek(1), Some('+' | '-')) { self.advance(); }
+
+            if !Scanner::is_digit(self.peek(1)) {
+                let error = format!("Invalid number {}: the exponent has no digits", self.lexeme());
+                self.invalid_number(&error);
+                return;
+            }
+
+            self.skip_digits();
         }
 
-        let val: f64 = self.chars[self.start..=self.current]
-                            .iter()
-                            .collect::<String>()
-                            .parse()
-                            .unwrap();
+        let lexeme = self.lexeme();
+
+        let error = Scanner::misplaced_separator(lexeme, |c| c.is_ascii_digit())
+            .or_else(|| number(lexeme).is_none().then(|| format!("Number {} is out of range", lexeme)));
+
+        match error {
+            Some(error) => self.invalid_number(&error),
+            None => self.add_token(TokenType::NUMBER),
+        }
+    }
+
+    /// Scans a `0x`, `0o` or `0b` number whose prefix letter is the current character.
+    fn handle_radix_number(&mut self, radix: u32, name: &str) {
+        // Take every letter and digit, so `0b102` is one bad number rather than `0b10` and `2`
+        while Scanner::is_alphanumeric(self.peek(1)) { self.advance(); }
+
+        let lexeme = self.lexeme();
+        let digits = &lexeme[2..];
+
+        let error = if digits.is_empty() {
+            Some(format!("Invalid number {}: there are no digits after the prefix", lexeme))
+        } else if let Some(c) = digits.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+            Some(format!("Invalid number {}: '{}' is not {} digit", lexeme, c, name))
+        } else {
+            // The prefix isn't a digit, so this rules out `0x_1` too
+            Scanner::misplaced_separator(lexeme, |c| c.is_digit(radix))
+                .or_else(|| number(lexeme).is_none().then(|| format!("Number {} is out of range", lexeme)))
+        };
+
+        match error {
+            Some(error) => self.invalid_number(&error),
+            None => self.add_token(TokenType::NUMBER),
+        }
+    }
+
+    /// Reports a malformed number, but still makes a token of it so the parser
+    /// doesn't report the same mistake again as a missing operand.
+    fn invalid_number(&mut self, message: &str) {
+        self.error(self.start_column, message);
+        self.add_token(TokenType::NUMBER);
+    }
+
+    /// Skips decimal digits and the `_`s that separate them.
+    fn skip_digits(&mut self) {
+        while Scanner::is_digit(self.peek(1)) || self.peek(1) == Some('_') { self.advance(); }
+    }
+
+    /// Describes what's wrong if `number` has a `_` that isn't between two digits.
+    fn misplaced_separator(number: &str, is_digit: impl Fn(char) -> bool) -> Option<String> {
+        let chars: Vec<char> = number.chars().collect();
+
+        let misplaced = (0..chars.len()).any(|i| {
+            chars[i] == '_' && !(i > 0 && is_digit(chars[i - 1]) && chars.get(i + 1).is_some_and(|c| is_digit(*c)))
+        });
+
+        if misplaced {
+            Some(format!("Invalid number {}: '_' must be between two digits", number))
+        } else {
+            None
+        }
+    }
+
+    /// The text of the token scanned so far.
+    fn lexeme(&self) -> &'a str {
+        &self.source[self.start..self.end()]
+    }
 
-        self.add_token(TokenType::NUMBER(val));
+    /// Where the token scanned so far ends, in bytes.
+    fn end(&self) -> usize {
+        self.current + self.current().map_or(0, char::len_utf8)
     }
 
     fn handle_identifier(&mut self) {
-        while Scanner::is_alphanumeric(self.next(1)) { self.advance(); }
+        while Scanner::is_alphanumeric(self.peek(1)) { self.advance(); }
 
-        let lexeme = self.chars[self.start..=self.current].iter().collect::<String>();
+        let lexeme = self.lexeme();
 
-        let token_type = self.key_words.get(&lexeme).map_or(TokenType::IDENTIFIER, |e| e.clone());
+        let token_type = self.key_words.get(lexeme).map_or(TokenType::IDENTIFIER, |e| e.clone());
 
         self.add_token(token_type);
     }
-}
\ No newline at end of file
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Result<Token<'a>, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_span().map(|result| result.map(|(token, _)| token))
+    }
+}
+
+/// Reports a scan error the way the command line does, and passes tokens through.
+fn reported(result: Result<Token<'_>, ScanError>) -> Option<Token<'_>> {
+    match result {
+        Ok(token) => Some(token),
+        Err(error) => {
+            loxerror::error(error.line, &error.message);
+            None
+        }
+    }
+}
+
+/// Where a stretch of text in a string literal starts.
+struct TextStart {
+    // In bytes
+    start: usize,
+    position: usize,
+    line: usize,
+    line_start: usize,
+}
+
+/// Decodes the escape sequences in the text of a string literal. Malformed
+/// ones are left out, and `error` is told the character offset of each one's
+/// backslash and what's wrong with it. Text without escapes isn't copied.
+pub fn unescape(text: &str, mut error: impl FnMut(usize, &str)) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut value = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '\\' {
+            value.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let backslash = i;
+        i += 1;
+
+        let escaped = match chars.get(i) {
+            // The string is unterminated, which is reported on its own
+            None => break,
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            // For a literal `${`
+            Some('$') => '$',
+            Some('x') => {
+                let digits: String = chars[i + 1..].iter().take(2).take_while(|c| c.is_ascii_hexdigit()).collect();
+
+                match u32::from_str_radix(&digits, 16) {
+                    Ok(n) if digits.len() == 2 && n <= 0x7F => {
+                        i += 3;
+                        value.push(char::from(n as u8));
+                    },
+                    Ok(_) if digits.len() == 2 => {
+                        i += 3;
+                        error(backslash, "Invalid escape sequence: \\x only goes up to \\x7F, use \\u{...} beyond that");
+                    },
+                    _ => {
+                        i += 1;
+                        error(backslash, "Invalid escape sequence: \\x must be followed by two hex digits");
+                    },
+                }
+
+                continue;
+            },
+            Some('u') => {
+                i += 1;
+
+                if chars.get(i) != Some(&'{') {
+                    error(backslash, "Invalid escape sequence: \\u must be followed by hex digits in braces, like \\u{1F600}");
+                    continue;
+                }
+
+                i += 1;
+
+                let digits: String = chars[i..].iter().take_while(|c| c.is_ascii_hexdigit()).collect();
+                i += digits.len();
+
+                if chars.get(i) != Some(&'}') || digits.is_empty() || digits.len() > 6 {
+                    error(backslash, "Invalid escape sequence: \\u must be followed by one to six hex digits in braces, like \\u{1F600}");
+                    continue;
+                }
+
+                i += 1;
+
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(c) => value.push(c),
+                    None => error(backslash, &format!("Invalid escape sequence: \\u{{{}}} is not a Unicode scalar value", digits)),
+                }
+
+                continue;
+            },
+            // Keep the newline, which is part of the string
+            Some('\n') => {
+                error(backslash, "Unknown escape sequence: \\ at the end of a line");
+                continue;
+            },
+            Some(c) => {
+                i += 1;
+                error(backslash, &format!("Unknown escape sequence: \\{}", c));
+                continue;
+            },
+        };
+
+        i += 1;
+        value.push(escaped);
+    }
+
+    Cow::Owned(value)
+}
+
+/// The value of a number literal, decimal or with a `0x`, `0o` or `0b` prefix,
+/// or None if it's malformed or too big.
+pub fn number(lexeme: &str) -> Option<f64> {
+    let digits = lexeme.replace('_', "");
+
+    let radix = match lexeme.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ => return digits.parse::<f64>().ok().filter(|n| n.is_finite()),
+    };
+
+    u64::from_str_radix(&digits[2..], radix).ok().map(|n| n as f64)
+}
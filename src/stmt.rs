@@ -0,0 +1,88 @@
+//! # Lox Statements
+//! 
+
+use std::fmt;
+use crate::token;
+use crate::expr::Expr;
+
+pub enum Stmt<'a> {
+    Expression(ExpressionStmt<'a>),
+    Print(PrintStmt<'a>),
+    Var(VarStmt<'a>),
+}
+
+impl Stmt<'_> {
+    /// The line the statement starts on.
+    pub fn line(&self) -> usize {
+        match self {
+            Stmt::Expression(e) => e.line,
+            Stmt::Print(p) => p.keyword.line,
+            Stmt::Var(v) => v.name.line,
+        }
+    }
+}
+
+pub struct ExpressionStmt<'a> {
+    pub expression: Expr<'a>,
+    // Literals don't keep their tokens, so the expression can't always tell us
+    pub line: usize,
+}
+
+impl<'a> ExpressionStmt<'a> {
+    pub fn new(expression: Expr<'a>, line: usize) -> Self {
+        ExpressionStmt {
+            expression,
+            line,
+        }
+    }
+}
+
+pub struct PrintStmt<'a> {
+    pub keyword: token::Token<'a>,
+    pub expression: Expr<'a>,
+}
+
+impl<'a> PrintStmt<'a> {
+    pub fn new(keyword: token::Token<'a>, expression: Expr<'a>) -> Self {
+        PrintStmt {
+            keyword,
+            expression,
+        }
+    }
+}
+
+pub struct VarStmt<'a> {
+    pub name: token::Token<'a>,
+    pub initializer: Option<Expr<'a>>,
+}
+
+impl<'a> VarStmt<'a> {
+    pub fn new(name: token::Token<'a>, initializer: Option<Expr<'a>>) -> Self {
+        VarStmt {
+            name,
+            initializer,
+        }
+    }
+}
+
+// Trait implementations
+
+// DISPLAY TRAIT
+impl fmt::Display for Stmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Expression(e) => write!(f, "(; {})", e.expression),
+            Stmt::Print(p) => write!(f, "(print {})", p.expression),
+            Stmt::Var(v) => v.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for VarStmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.initializer {
+            Some(i) => write!(f, "(var {} {})", self.name.lexeme(), i),
+            None => write!(f, "(var {})", self.name.lexeme()),
+        }
+    }
+}
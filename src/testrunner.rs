@@ -0,0 +1,218 @@
+//! # Lox Test Runner
+//!
+//! `rlox test <dir>` runs every `.lox` script under a directory and checks it
+//! against the expectations written in its comments, the way the reference
+//! Lox test suite does:
+//!
+//! - `// expect: <value>` is a line the script prints
+//! - `// expect runtime error: <message>` is the error the script stops with,
+//!   on the line the comment is on
+//! - `// [line N] Error ...` is an error the scanner or parser reports
+//!
+//! Scripts run in-process. What they print is captured through the
+//! interpreter's output, and errors are collected instead of printed.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::interpreter::Interpreter;
+use crate::lox::EX_NOINPUT;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::token::TokenType;
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_ERROR: &str = "// [line ";
+
+/// Runs the scripts under `path`, which may also be a single script, and
+/// returns the exit code the process should use.
+pub fn run(path: &str) -> i32 {
+    let mut scripts = Vec::new();
+
+    if let Err(e) = find_scripts(Path::new(path), &mut scripts) {
+        eprintln!("Could not read {}: {}", path, e);
+        return EX_NOINPUT;
+    }
+
+    scripts.sort();
+
+    let mut failed = 0;
+
+    for script in &scripts {
+        let name = script.display();
+
+        let source = match fs::read_to_string(script) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("FAIL {}\n    Could not read it: {}", name, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let expected = expectations(&source);
+        let actual = transcript(&source);
+
+        if expected == actual {
+            println!("PASS {}", name);
+        } else {
+            println!("FAIL {}", name);
+
+            for line in diff(&expected, &actual) {
+                println!("    {}", line);
+            }
+
+            failed += 1;
+        }
+    }
+
+    println!("\n{} passed, {} failed, {} total", scripts.len() - failed, failed, scripts.len());
+
+    if failed > 0 { 1 } else { 0 }
+}
+
+fn find_scripts(path: &Path, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        // Reading the metadata makes a path that doesn't exist an error
+        fs::metadata(path)?;
+        scripts.push(path.to_path_buf());
+
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|e| e == "lox") {
+            scripts.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// What the script's comments say running it should produce. Printed lines
+/// come first, then the errors, in the same form `transcript` uses.
+pub fn expectations(source: &str) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        if let Some(start) = line.find(EXPECT) {
+            output.push(String::from(&line[start + EXPECT.len()..]));
+        } else if let Some(start) = line.find(EXPECT_RUNTIME_ERROR) {
+            errors.push(format!("[line {}] Runtime error: {}", i + 1, &line[start + EXPECT_RUNTIME_ERROR.len()..]));
+        } else if let Some(start) = line.find(EXPECT_ERROR) {
+            // Keep the `[line N]` from the comment
+            errors.push(String::from(&line[start + 3..]));
+        }
+    }
+
+    output.extend(errors);
+    output
+}
+
+/// Runs `source` and describes what happened: every line it printed, then
+/// every error it stopped with.
+pub fn transcript(source: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let (tokens, scan_errors) = Scanner::new(source).scan_tokens_quietly();
+
+    for e in scan_errors {
+        errors.push((e.line, format!("Error: {}", e.message)));
+    }
+
+    let statements = match Parser::new(tokens).parse() {
+        Ok(statements) => statements,
+        Err(parse_errors) => {
+            for e in parse_errors {
+                errors.push(match e.token() {
+                    Some(t) if t.token_type == TokenType::EOF => (t.line, String::from("Error at end: Unexpected end of file")),
+                    Some(t) => (t.line, format!("Error at '{}': {}", t.lexeme(), e.message())),
+                    None => (0, String::from("Error at end: Unexpected end of file")),
+                });
+            }
+
+            Vec::new()
+        }
+    };
+
+    // Like the command line, don't run a program that didn't compile. The
+    // scanner and parser each report in order, so merge them by line.
+    if !errors.is_empty() {
+        errors.sort_by_key(|(line, _)| *line);
+
+        return errors.into_iter().map(|(line, e)| format!("[line {}] {}", line, e)).collect();
+    }
+
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(Box::new(Capture(Rc::clone(&output))));
+
+    let result = interpreter.interpret(&statements);
+    drop(interpreter);
+
+    let output = String::from_utf8_lossy(&output.borrow()).into_owned();
+    let mut lines: Vec<String> = output.lines().map(String::from).collect();
+
+    if let Err(e) = result {
+        lines.push(format!("[line {}] Runtime error: {}", e.line(), e.message()));
+    }
+
+    lines
+}
+
+/// The lines of a shortest edit from `expected` to `actual`, marking lines
+/// only expected with `-` and lines that only happened with `+`.
+pub fn diff(expected: &[String], actual: &[String]) -> Vec<String> {
+    // lengths[i][j] is the longest common subsequence of expected[i..] and actual[j..]
+    let mut lengths = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+/// Collects what a script prints.
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,46 +1,154 @@
 //! # Lox tokens
 //! 
 
+use std::borrow::Cow;
+use std::fmt;
+use crate::scanner;
+
+/// A token, borrowing the slice of the source it was scanned from, so
+/// neither scanning nor cloning a token copies its text.
 #[derive(Debug, Clone)]
-pub struct Token {
-    pub token_type: TokenType,
-    pub lexeme: String,
+pub struct Token<'a> {
+    pub token_type: TokenType<'a>,
+    lexeme: &'a str,
     pub line: usize,
+    // Counted in characters, starting from 1
+    pub column: usize,
 }
 
-impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, line: usize) -> Self {
+impl<'a> Token<'a> {
+    pub fn new(token_type: TokenType<'a>, lexeme: &'a str, line: usize, column: usize) -> Self {
         Token {
             token_type,
             lexeme,
+            line,
+            column
+        }
+    }
+
+    /// The text the token was scanned from.
+    pub fn lexeme(&self) -> &'a str {
+        self.lexeme
+    }
+
+    /// The value of a STRING token, with its escape sequences decoded. Only
+    /// strings with escapes in them need a copy. Malformed escapes, which the
+    /// scanner already reported, are left out.
+    pub fn string(&self) -> Cow<'a, str> {
+        let lexeme = self.lexeme();
+        let text = lexeme.strip_prefix('"').unwrap_or(lexeme);
+
+        scanner::unescape(text.strip_suffix('"').unwrap_or(text), |_, _| {})
+    }
+
+    /// The value of a NUMBER token. NaN for a malformed number, which the
+    /// scanner already reported.
+    pub fn number(&self) -> f64 {
+        scanner::number(self.lexeme()).unwrap_or(f64::NAN)
+    }
+}
+
+// Trait implementations
+
+impl PartialEq for Token<'_> {
+    // Tokens are equal when they look the same, whichever source they came from
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type
+            && self.lexeme() == other.lexeme()
+            && self.line == other.line
+            && self.column == other.column
+    }
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {}", self.token_type, self.lexeme())
+    }
+}
+
+/// A piece of an interpolated string: either text, or the tokens of an
+/// expression to splice in. The tokens end with an EOF of their own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart<'a> {
+    Text(String),
+    Code(Vec<Token<'a>>),
+}
+
+/// A `//` or `/* */` comment. The scanner doesn't turn these into tokens, but
+/// tools like the formatter need to know where they were.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+}
+
+impl Comment {
+    pub fn new(text: String, line: usize) -> Self {
+        Comment {
+            text,
             line
         }
     }
 
-    pub fn to_string(&self) -> String {
-        format!("{:?} {}", self.token_type, self.lexeme)
+    /// The line the comment ends on, which for a block comment may be past the one it starts on.
+    pub fn end_line(&self) -> usize {
+        self.line + self.text.matches('\n').count()
     }
 }
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, PartialEq)]
-pub enum TokenType {
+pub enum TokenType<'a> {
     // Single-character tokens.
     LEFT_PAREN, RIGHT_PAREN, LEFT_BRACE, RIGHT_BRACE,
-    COMMA, DOT, MINUS, PLUS, SEMICOLON, SLASH, STAR, 
+    COMMA, DOT, MINUS, PLUS, SEMICOLON, SLASH, QUESTION, COLON,
+    PERCENT, AMPERSAND, PIPE, CARET,
 
     // One or two character tokens.
     BANG, BANG_EQUAL,
     EQUAL, EQUAL_EQUAL,
-    GREATER, GREATER_EQUAL,
-    LESS, LESS_EQUAL,
+    GREATER, GREATER_EQUAL, GREATER_GREATER,
+    LESS, LESS_EQUAL, LESS_LESS,
+    STAR, STAR_STAR,
+    TILDE, TILDE_SLASH,
 
-    // Literals.
-    IDENTIFIER, STRING(String), NUMBER(f64),
+    // Literals. Their values are decoded from the lexeme when asked for.
+    IDENTIFIER, STRING, NUMBER,
+    // A string with `${...}` in it
+    INTERPOLATION(Vec<StringPart<'a>>),
 
     // Keywords.
     AND, CLASS, ELSE, FALSE, FUN, FOR, IF, NIL, OR,
     PRINT, RETURN, SUPER, THIS, TRUE, VAR, WHILE,
 
     EOF
-}
\ No newline at end of file
+}
+
+impl TokenType<'_> {
+    /// How tightly this token binds as a binary operator, from 1 (`==`, `!=`)
+    /// up to 9 (`**`). Tokens that aren't binary operators get 0.
+    ///
+    /// Bitwise operators bind tighter than comparisons, so `a & 1 == 0` tests
+    /// the masked bits, and `**` binds tighter than a unary minus on its left,
+    /// so `-2 ** 2` is -4.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            TokenType::BANG_EQUAL | TokenType::EQUAL_EQUAL => 1,
+            TokenType::GREATER | TokenType::GREATER_EQUAL | TokenType::LESS | TokenType::LESS_EQUAL => 2,
+            TokenType::PIPE => 3,
+            TokenType::CARET => 4,
+            TokenType::AMPERSAND => 5,
+            TokenType::LESS_LESS | TokenType::GREATER_GREATER => 6,
+            TokenType::MINUS | TokenType::PLUS => 7,
+            TokenType::SLASH | TokenType::STAR | TokenType::PERCENT | TokenType::TILDE_SLASH => 8,
+            TokenType::STAR_STAR => 9,
+            _ => 0,
+        }
+    }
+
+    /// Whether a chain of this operator groups from the right, like `**`:
+    /// `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+    pub fn is_right_associative(&self) -> bool {
+        *self == TokenType::STAR_STAR
+    }
+}
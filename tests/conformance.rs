@@ -0,0 +1,163 @@
+//! Runs the Lox programs under `tests/lox` and checks each one against the
+//! `// expect` comments in it, the same way `rlox test` does.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rlox::cst::parse;
+use rlox::parser::{Parser, ParserErrorKind};
+use rlox::scanner::{Scanner, ScanError};
+use rlox::testrunner::{diff, expectations, transcript};
+use rlox::token::TokenType::{self, *};
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|e| e == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+#[test]
+fn corpus() {
+    let mut found = Vec::new();
+    scripts(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"), &mut found);
+    found.sort();
+
+    let mut failures = Vec::new();
+
+    for script in &found {
+        let source = fs::read_to_string(script).unwrap();
+        let expected = expectations(&source);
+        let actual = transcript(&source);
+
+        if expected != actual {
+            failures.push(format!("{}\n    {}", script.display(), diff(&expected, &actual).join("\n    ")));
+        }
+    }
+
+    assert!(!found.is_empty(), "no scripts found");
+    assert!(failures.is_empty(), "{} of {} scripts failed:\n{}", failures.len(), found.len(), failures.join("\n"));
+}
+
+#[test]
+fn runner_summary_and_exit_code() {
+    let dir = std::env::temp_dir().join(format!("rlox-runner-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pass.lox"), "print 1 + 1; // expect: 2\n").unwrap();
+    fs::write(dir.join("fail.lox"), "print 1 + 1; // expect: 3\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox")).arg("test").arg(&dir).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.lines().any(|l| l.starts_with("PASS ") && l.ends_with("pass.lox")), "{}", stdout);
+    assert!(stdout.lines().any(|l| l.starts_with("FAIL ") && l.ends_with("fail.lox")), "{}", stdout);
+    assert!(stdout.ends_with("\n1 passed, 1 failed, 2 total\n"), "{}", stdout);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn every_token_type() {
+    let source = "( ) { } , . - + ; / ? : % & | ^ ! != = == > >= >> < <= << * ** ~ ~/ name \"text\" 1.5 \
+                  and class else false fun for if nil or print return super this true var while";
+
+    let (tokens, errors) = Scanner::new(source).scan_tokens_quietly();
+    let types: Vec<TokenType> = tokens.into_iter().map(|t| t.token_type).collect();
+
+    assert!(errors.is_empty());
+    assert_eq!(types, vec![
+        LEFT_PAREN, RIGHT_PAREN, LEFT_BRACE, RIGHT_BRACE, COMMA, DOT, MINUS, PLUS, SEMICOLON, SLASH, QUESTION, COLON,
+        PERCENT, AMPERSAND, PIPE, CARET,
+        BANG, BANG_EQUAL, EQUAL, EQUAL_EQUAL, GREATER, GREATER_EQUAL, GREATER_GREATER, LESS, LESS_EQUAL, LESS_LESS,
+        STAR, STAR_STAR, TILDE, TILDE_SLASH,
+        IDENTIFIER, STRING, NUMBER,
+        AND, CLASS, ELSE, FALSE, FUN, FOR, IF, NIL, OR, PRINT, RETURN, SUPER, THIS, TRUE, VAR, WHILE,
+        EOF,
+    ]);
+}
+
+#[test]
+fn token_positions() {
+    let (tokens, _) = Scanner::new("var a =\n  \"two\nlines\" ;").scan_tokens_quietly();
+    let positions: Vec<(usize, usize)> = tokens.iter().map(|t| (t.line, t.column)).collect();
+
+    // A token is on the line it starts on
+    assert_eq!(positions, vec![(1, 1), (1, 5), (1, 7), (2, 3), (3, 8), (3, 9)]);
+}
+
+#[test]
+fn unicode_positions() {
+    let (tokens, errors) = Scanner::new("\u{FEFF}var ñandú =\n\u{3000}\"日本\" ;").scan_tokens_quietly();
+    let positions: Vec<(usize, usize)> = tokens.iter().map(|t| (t.line, t.column)).collect();
+
+    // Columns count characters, the byte order mark included
+    assert!(errors.is_empty());
+    assert_eq!(positions, vec![(1, 2), (1, 6), (1, 12), (2, 2), (2, 7), (2, 8)]);
+}
+
+#[test]
+fn byte_order_mark_round_trips() {
+    let source = "\u{FEFF}print\u{A0}1; // ok\n";
+
+    assert_eq!(parse(source).to_string(), source);
+}
+
+#[test]
+fn streaming_scanner() {
+    let mut scanner = Scanner::new("print @ 1;");
+
+    assert_eq!(scanner.next().map(|r| r.map(|t| t.token_type)), Some(Ok(PRINT)));
+    assert!(matches!(scanner.next(), Some(Err(e)) if e.column == 7));
+
+    // The rest are scanned as they're asked for, ending with EOF
+    let rest: Vec<TokenType> = scanner.map(|r| r.unwrap().token_type).collect();
+    assert_eq!(rest, vec![NUMBER, SEMICOLON, EOF]);
+}
+
+#[test]
+fn tokens_borrow_the_source() {
+    let source = String::from("print naïve;");
+    let tokens = Scanner::new(&source).scan_tokens();
+
+    // Every token points into the caller's copy of the source
+    assert_eq!(tokens[1].lexeme().as_ptr(), source[6..].as_ptr());
+    assert_eq!(tokens[2].lexeme().as_ptr(), source[12..].as_ptr());
+}
+
+#[test]
+fn interpolation_nested_too_deeply_is_one_error() {
+    // The innermost code has a string with code of its own, and braces
+    let mut literal = String::from("\"${1 + \"${\"}\"}\"}\"");
+
+    for _ in 0..16 {
+        literal = format!("\"${{{}}}\"", literal);
+    }
+
+    let source = format!("print {};\nprint 2;", literal);
+    let (tokens, errors) = Scanner::new(&source).scan_tokens_quietly();
+    let types: Vec<TokenType> = tokens.iter().map(|t| t.token_type.clone()).collect();
+
+    assert_eq!(errors, vec![ScanError::new(1, 56, "Interpolated strings nest too deeply")]);
+    assert_eq!(types[types.len() - 5..], [SEMICOLON, PRINT, NUMBER, SEMICOLON, EOF]);
+    assert!(Parser::new(tokens).parse().is_ok());
+}
+
+#[test]
+fn parser_error_kinds() {
+    let kinds = |source: &str| -> Vec<ParserErrorKind> {
+        let (tokens, _) = Scanner::new(source).scan_tokens_quietly();
+        Parser::new(tokens).parse().err().unwrap_or_default().iter().map(|e| e.kind()).collect()
+    };
+
+    let deep = format!("print {}1{};", "(".repeat(101), ")".repeat(101));
+
+    assert_eq!(kinds("print 1 +;"), vec![ParserErrorKind::UnexpectedToken]);
+    assert_eq!(kinds(&deep), vec![ParserErrorKind::TooDeep]);
+    assert_eq!(kinds("print 1;"), vec![]);
+}
@@ -0,0 +1,118 @@
+//! Checks the coverage reports for small programs whose coverage is known.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use rlox::coverage::{self, Coverage, CoverageHook};
+use rlox::interpreter::Interpreter;
+use rlox::lox::EX_DATAERR;
+use rlox::parser::Parser;
+use rlox::scanner::Scanner;
+
+const FILE: &str = "test.lox";
+
+fn cover(source: &str) -> Coverage {
+    let (tokens, _) = Scanner::new(source).scan_tokens_quietly();
+    let statements = Parser::new(tokens).parse().unwrap_or_else(|_| panic!("{} doesn't parse", source));
+
+    let coverage = Rc::new(RefCell::new(Coverage::default()));
+    coverage.borrow_mut().add_file(FILE, source, &statements);
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(Box::new(io::sink()));
+    interpreter.set_hook(Box::new(CoverageHook::new(&coverage, FILE)));
+
+    // A runtime error ends the run but not the coverage
+    let _ = interpreter.interpret(&statements);
+    drop(interpreter);
+
+    Rc::try_unwrap(coverage).ok().unwrap().into_inner()
+}
+
+#[test]
+fn lcov_lines_and_branches() {
+    let coverage = cover("var a = 1;\nprint a > 0 ? \"yes\" : \"no\";\nprint a > 0 ? \"yes\" : \"no\";\n");
+
+    assert_eq!(coverage.lcov(), "\
+SF:test.lox
+BRDA:2,0,0,1
+BRDA:2,0,1,0
+BRDA:3,0,0,1
+BRDA:3,0,1,0
+BRF:4
+BRH:2
+DA:1,1
+DA:2,1
+DA:3,1
+LF:3
+LH:3
+end_of_record
+");
+}
+
+#[test]
+fn branches_never_reached_are_reported() {
+    let coverage = cover("var a = false;\nprint a ? (true ? 1 : 2) : 3;\n");
+    let lcov = coverage.lcov();
+
+    // The inner conditional never ran, so neither of its branches did
+    assert!(lcov.contains("BRDA:2,0,0,0\nBRDA:2,0,1,1\nBRDA:2,1,0,-\nBRDA:2,1,1,-\n"), "{}", lcov);
+    assert!(lcov.contains("BRF:4\nBRH:1\n"), "{}", lcov);
+}
+
+#[test]
+fn lines_after_an_error_are_missed() {
+    let coverage = cover("print 1;\nprint -nil;\nprint 3;\nprint true ? 1 : 2;\n");
+    let file = &coverage.files[FILE];
+
+    assert_eq!(file.missed(), vec![3, 4]);
+    assert!(coverage.lcov().contains("DA:3,0\nDA:4,0\nLF:4\nLH:2\n"));
+    assert!(coverage.lcov().contains("BRDA:4,0,0,-\nBRDA:4,0,1,-\nBRF:2\nBRH:0\n"));
+}
+
+#[test]
+fn annotated_listing() {
+    let coverage = cover("// setup\nvar a = 1;\n\nprint a == 1 ? 1 : 2;\nprint -nil;\nprint a;\n");
+
+    let expected = [
+        "test.lox",
+        "        -:    1: // setup",
+        "        1:    2: var a = 1;",
+        "        -:    3: ",
+        "        1:    4: print a == 1 ? 1 : 2;",
+        "        1:    5: print -nil;",
+        "    #####:    6: print a;",
+        "",
+        "Lines: 3/4 (75.0%)",
+        "Branches: 1/2",
+        "Never executed: 6",
+        "",
+        "",
+    ];
+
+    assert_eq!(coverage.annotated(), expected.join("\n"));
+}
+
+#[test]
+fn lcov_goes_to_the_path_given() {
+    let path = std::env::temp_dir().join(format!("rlox-coverage-{}.info", std::process::id()));
+    let code = coverage::run(FILE, "print 1;", path.to_str().unwrap());
+
+    let lcov = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(code, 0);
+    assert!(lcov.starts_with("SF:test.lox\n"));
+    assert!(lcov.contains("DA:1,1\n"));
+}
+
+#[test]
+fn a_failed_run_doesnt_fail_the_next() {
+    let path = std::env::temp_dir().join(format!("rlox-coverage-{}-after-failure.info", std::process::id()));
+
+    assert_eq!(coverage::run(FILE, "print @;", path.to_str().unwrap()), EX_DATAERR);
+    assert_eq!(coverage::run(FILE, "print 1;", path.to_str().unwrap()), 0);
+
+    fs::remove_file(&path).unwrap();
+}
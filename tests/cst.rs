@@ -0,0 +1,132 @@
+//! Checks that the concrete syntax tree keeps every character of its source,
+//! in the right place, whatever shape the source is in.
+
+use rlox::cst::{parse, tokenize, NodeKind, SyntaxElement, SyntaxNode, TriviaKind};
+use rlox::token::TokenType::*;
+
+fn kinds(node: &SyntaxNode, found: &mut Vec<NodeKind>) {
+    found.push(node.kind);
+
+    for child in &node.children {
+        if let SyntaxElement::Node(n) = child {
+            kinds(n, found);
+        }
+    }
+}
+
+fn statements(node: &SyntaxNode) -> Vec<NodeKind> {
+    node.children.iter().filter_map(|c| match c {
+        SyntaxElement::Node(n) => Some(n.kind),
+        SyntaxElement::Token(_) => None,
+    }).collect()
+}
+
+#[test]
+fn whitespace_round_trips() {
+    let sources = [
+        "",
+        "   \n\n\t",
+        "print 1;",
+        "  print\t1 ;  \n",
+        "var a\r\n  =\r\n  1 ;\r\n",
+        "print -  ( 1+2 )*3 ;\n\n\nprint a=b;",
+        "print \"two\nlines\";\n",
+        "\u{FEFF}var naïve\u{3000}= \"日本\" ;\n",
+    ];
+
+    for source in sources {
+        assert_eq!(parse(source).to_string(), source);
+    }
+}
+
+#[test]
+fn comments_round_trip() {
+    let sources = [
+        "// only a comment",
+        "print 1; // after\n// before\nprint 2;\n",
+        "print /* inside */ 1;",
+        "/* outer /* nested */ still outer */ print 1;",
+        "print 1; /* never closed",
+        "print 1; // café ☕\n/* π /* ≈ */ 3.14 */ print 2;",
+        "print 1 @ ¤ 2; /* naïve",
+    ];
+
+    for source in sources {
+        assert_eq!(parse(source).to_string(), source);
+    }
+}
+
+#[test]
+fn comments_belong_to_the_nearest_token() {
+    let tokens = tokenize("print 1; // after\n// before\nprint 2;");
+
+    // A comment on the same line trails the token before it...
+    let semicolon = &tokens[2];
+    assert_eq!(semicolon.token.token_type, SEMICOLON);
+    assert!(semicolon.trailing.iter().any(|t| t.kind == TriviaKind::Comment && t.text == "// after"));
+
+    // ...and one on a line of its own leads the token after it
+    let print = &tokens[3];
+    assert_eq!(print.token.token_type, PRINT);
+    assert!(print.leading.iter().any(|t| t.kind == TriviaKind::Comment && t.text == "// before"));
+
+    assert_eq!(tokens.last().map(|t| &t.token.token_type), Some(&EOF));
+}
+
+#[test]
+fn errors_round_trip_and_recover() {
+    let source = "print 1 +;\nvar = 2;\nprint ) 3;\n@ print 4;\nprint (5;\nprint 6;";
+    let tree = parse(source);
+
+    assert_eq!(tree.to_string(), source);
+
+    let mut found = Vec::new();
+    kinds(&tree, &mut found);
+    assert!(found.contains(&NodeKind::Error));
+
+    // Parsing carries on after each error, so the last statement is still whole
+    let last = tree.children.iter().rev().find_map(|c| match c {
+        SyntaxElement::Node(n) => Some(n),
+        SyntaxElement::Token(_) => None,
+    }).unwrap();
+    assert_eq!(last.kind, NodeKind::PrintStmt);
+    assert_eq!(last.to_string(), "\nprint 6;");
+
+    let mut in_last = Vec::new();
+    kinds(last, &mut in_last);
+    assert!(!in_last.contains(&NodeKind::Error));
+}
+
+#[test]
+fn nesting_is_limited_like_the_parser() {
+    let within = format!("print {}1{};", "(".repeat(100), ")".repeat(100));
+    let mut found = Vec::new();
+    kinds(&parse(&within), &mut found);
+    assert!(!found.contains(&NodeKind::Error));
+
+    let too_deep = format!("print {}1{};", "(".repeat(101), ")".repeat(101));
+    let mut found = Vec::new();
+    kinds(&parse(&too_deep), &mut found);
+    assert!(found.contains(&NodeKind::Error));
+}
+
+#[test]
+fn deep_nesting_round_trips() {
+    let n = 100_000;
+    let sources = [
+        format!("print {}1{}; print 2;", "(".repeat(n), ")".repeat(n)),
+        format!("print {}1; print 2;", "-".repeat(n)),
+        format!("print {}; print 2;", vec!["1"; n].join(" + ")),
+        format!("print {}; print 2;", vec!["1"; n].join(" ** ")),
+        format!("var a; {}1; print 2;", "a = ".repeat(n)),
+        format!("print {}; print 2;", vec!["1"; n].join(", ")),
+    ];
+
+    for source in &sources {
+        let tree = parse(source);
+
+        assert_eq!(tree.to_string(), *source);
+        // The statement after the one that went too deep is untouched
+        assert_eq!(statements(&tree).last(), Some(&NodeKind::PrintStmt));
+    }
+}
@@ -0,0 +1,246 @@
+//! Debugs programs through the debug adapter the way an editor would, with
+//! scripted requests and the responses and events they get back.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use serde_json::{json, Value};
+use rlox::dap::{serve, Adapter};
+use rlox::lsp::{read_message, write_message};
+
+const SOURCE: &str = "var a = 1;\nprint a;\na = a + 1;\nprint a;\n";
+
+/// Collects what's written to it where the test can still read it.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A script on disk for `launch`, removed when the test is done with it.
+struct Script(PathBuf);
+
+impl Script {
+    fn new(name: &str, source: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rlox-dap-{}-{}.lox", std::process::id(), name));
+        fs::write(&path, source).unwrap();
+        Script(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Serves `requests` to the end, returning every message sent back.
+fn session(requests: &[Value]) -> Vec<Value> {
+    serve_requests(requests).1
+}
+
+/// Like `session`, but also hands back the adapter that served them.
+fn serve_requests(requests: &[Value]) -> (Rc<RefCell<Adapter>>, Vec<Value>) {
+    let mut input = Vec::new();
+
+    for (seq, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["seq"] = json!(seq + 1);
+        request["type"] = json!("request");
+        write_message(&mut input, &request).unwrap();
+    }
+
+    let output = Capture::default();
+    let adapter = Rc::new(RefCell::new(Adapter::new(Box::new(Cursor::new(input)), Box::new(output.clone()))));
+    serve(&adapter).unwrap();
+
+    let mut output = Cursor::new(output.0.borrow().clone());
+    let mut messages = Vec::new();
+
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message.unwrap());
+    }
+
+    (adapter, messages)
+}
+
+fn request(command: &str, arguments: Value) -> Value {
+    json!({ "command": command, "arguments": arguments })
+}
+
+/// The response to the `seq`th request, counting from 1.
+fn response(messages: &[Value], seq: i64) -> &Value {
+    messages.iter()
+        .find(|m| m["type"] == "response" && m["request_seq"] == seq)
+        .unwrap_or_else(|| panic!("no response to request {}", seq))
+}
+
+fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|m| m["type"] == "event" && m["event"] == event).collect()
+}
+
+fn output(messages: &[Value]) -> String {
+    events(messages, "output").iter()
+        .filter(|e| e["body"]["category"] == "stdout")
+        .map(|e| e["body"]["output"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn breakpoint_inspect_and_continue() {
+    let script = Script::new("breakpoint", SOURCE);
+
+    let messages = session(&[
+        request("initialize", json!({ "adapterID": "rlox" })),
+        request("launch", json!({ "program": script.path() })),
+        request("setBreakpoints", json!({ "source": { "path": script.path() }, "breakpoints": [{ "line": 3 }, { "line": 9 }] })),
+        request("configurationDone", Value::Null),
+        request("stackTrace", json!({ "threadId": 1 })),
+        request("evaluate", json!({ "expression": "a * 10", "frameId": 0 })),
+        request("variables", json!({ "variablesReference": 1 })),
+        request("continue", json!({ "threadId": 1 })),
+        request("disconnect", Value::Null),
+    ]);
+
+    assert_eq!(response(&messages, 1)["body"]["supportsConfigurationDoneRequest"], true);
+    assert_eq!(events(&messages, "initialized").len(), 1);
+    assert_eq!(response(&messages, 3)["body"]["breakpoints"], json!([
+        { "verified": true, "line": 3 },
+        { "verified": false, "line": 9 },
+    ]));
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+    let frames = &response(&messages, 5)["body"]["stackFrames"];
+    assert_eq!(frames[0]["line"], 3);
+    assert_eq!(frames[0]["source"]["path"], script.path());
+
+    assert_eq!(response(&messages, 6)["body"]["result"], "10");
+    assert_eq!(response(&messages, 7)["body"]["variables"], json!([{ "name": "a", "value": "1", "variablesReference": 0 }]));
+    assert_eq!(response(&messages, 8)["success"], true);
+
+    assert_eq!(output(&messages), "1\n2\n");
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+}
+
+#[test]
+fn stop_on_entry_and_step() {
+    let script = Script::new("step", SOURCE);
+
+    let messages = session(&[
+        request("launch", json!({ "program": script.path(), "stopOnEntry": true })),
+        request("configurationDone", Value::Null),
+        request("next", json!({ "threadId": 1 })),
+        request("stackTrace", json!({ "threadId": 1 })),
+        request("disconnect", Value::Null),
+    ]);
+
+    let reasons: Vec<&Value> = events(&messages, "stopped").iter().map(|e| &e["body"]["reason"]).collect();
+    assert_eq!(reasons, vec!["entry", "step"]);
+    assert_eq!(response(&messages, 4)["body"]["stackFrames"][0]["line"], 2);
+
+    // Disconnecting stops the program without it being the program's fault
+    assert_eq!(output(&messages), "");
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+}
+
+#[test]
+fn runtime_errors_are_reported() {
+    let script = Script::new("error", "print 1;\nprint -nil;\n");
+
+    let messages = session(&[
+        request("launch", json!({ "program": script.path() })),
+        request("configurationDone", Value::Null),
+    ]);
+
+    let stderr: Vec<&Value> = events(&messages, "output").into_iter().filter(|e| e["body"]["category"] == "stderr").collect();
+    assert!(stderr[0]["body"]["output"].as_str().unwrap().contains("Operand must be a number"));
+    assert_ne!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+}
+
+#[test]
+fn launch_arguments_are_accepted() {
+    let script = Script::new("args", SOURCE);
+
+    let (adapter, messages) = serve_requests(&[
+        request("launch", json!({ "program": script.path(), "args": ["--verbose", "input.txt"] })),
+        request("configurationDone", Value::Null),
+    ]);
+
+    assert_eq!(response(&messages, 1)["success"], true);
+    assert_eq!(adapter.borrow().args(), ["--verbose", "input.txt"]);
+    assert_eq!(output(&messages), "1\n2\n");
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+
+    let messages = session(&[
+        request("launch", json!({ "program": script.path(), "args": [1] })),
+        request("launch", json!({ "program": script.path(), "args": "--verbose" })),
+    ]);
+
+    assert_eq!(response(&messages, 1)["message"], "Program arguments must be strings");
+    assert_eq!(response(&messages, 2)["message"], "Program arguments must be a list");
+}
+
+#[test]
+fn unknown_frames_are_refused() {
+    let script = Script::new("frames", SOURCE);
+
+    let messages = session(&[
+        request("launch", json!({ "program": script.path(), "stopOnEntry": true })),
+        request("configurationDone", Value::Null),
+        request("evaluate", json!({ "expression": "1", "frameId": 5 })),
+        request("evaluate", json!({ "expression": "1", "frameId": "main" })),
+        request("evaluate", json!({ "expression": "1" })),
+        request("disconnect", Value::Null),
+    ]);
+
+    assert_eq!(response(&messages, 3)["message"], "Unknown frame 5");
+    assert_eq!(response(&messages, 4)["message"], "Unknown frame \"main\"");
+    assert_eq!(response(&messages, 5)["body"]["result"], "1");
+}
+
+#[test]
+fn requests_need_a_paused_program() {
+    let messages = session(&[
+        request("evaluate", json!({ "expression": "1" })),
+        request("launch", json!({})),
+        request("restart", Value::Null),
+    ]);
+
+    assert_eq!(response(&messages, 1)["message"], "Can't evaluate while the program isn't paused");
+    assert_eq!(response(&messages, 2)["message"], "Missing program to launch");
+    assert_eq!(response(&messages, 3)["message"], "Unsupported request restart");
+}
+
+#[test]
+fn unreadable_messages_are_skipped() {
+    let mut input = b"Content-Length: 3\r\n\r\n{{{".to_vec();
+    write_message(&mut input, &json!({ "seq": 1, "type": "request", "command": "threads" })).unwrap();
+
+    let output = Capture::default();
+    let adapter = Rc::new(RefCell::new(Adapter::new(Box::new(Cursor::new(input)), Box::new(output.clone()))));
+    serve(&adapter).unwrap();
+
+    let mut output = Cursor::new(output.0.borrow().clone());
+    let first = read_message(&mut output).unwrap().unwrap().unwrap();
+    let second = read_message(&mut output).unwrap().unwrap().unwrap();
+
+    assert_eq!(first["body"]["category"], "console");
+    assert_eq!(second["body"]["threads"][0]["id"], 1);
+}
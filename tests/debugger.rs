@@ -0,0 +1,143 @@
+//! Drives the debugger with scripted commands and checks where it stops and
+//! what it says.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+use rlox::debugger::{self, Debugger};
+use rlox::interpreter::{Interpreter, RuntimeErrorKind};
+use rlox::lox::EX_DATAERR;
+use rlox::parser::Parser;
+use rlox::scanner::Scanner;
+
+const SOURCE: &str = "var a = 1;\nprint a;\na = a + 1;\nprint a;\n";
+
+/// Collects what's written to it where the test can still read it.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `source` under the debugger with `commands` as its input. Returns how
+/// the program ended, what the debugger said and what the program printed.
+fn debug(source: &str, commands: &str) -> (Option<RuntimeErrorKind>, String, String) {
+    let (tokens, _) = Scanner::new(source).scan_tokens_quietly();
+    let statements = Parser::new(tokens).parse().unwrap_or_else(|_| panic!("{} doesn't parse", source));
+
+    let said = Capture::default();
+    let printed = Capture::default();
+    let input = Box::new(Cursor::new(commands.to_string().into_bytes()));
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(Box::new(printed.clone()));
+    interpreter.set_hook(Box::new(Debugger::new("test.lox", source, input, Box::new(said.clone()))));
+
+    let result = interpreter.interpret(&statements);
+
+    (result.err().map(|e| e.kind()), said.text(), printed.text())
+}
+
+#[test]
+fn breakpoint_and_continue() {
+    let (result, said, printed) = debug(SOURCE, "b 3\nc\np a\nc\n");
+
+    assert_eq!(result, None);
+    assert_eq!(said, "\
+test.lox:1: var a = 1;
+(debug) Breakpoint set at test.lox:3
+(debug) test.lox:3: a = a + 1;
+(debug) a = 1
+(debug) ");
+    assert_eq!(printed, "1\n2\n");
+}
+
+#[test]
+fn next_stops_at_each_statement() {
+    let (result, said, printed) = debug(SOURCE, "n\n\nq\n");
+
+    // An empty line repeats `next`
+    assert_eq!(result, Some(RuntimeErrorKind::Stopped));
+    assert_eq!(said, "\
+test.lox:1: var a = 1;
+(debug) test.lox:2: print a;
+(debug) test.lox:3: a = a + 1;
+(debug) ");
+    assert_eq!(printed, "1\n");
+}
+
+#[test]
+fn step_stops_at_expressions() {
+    let (_, said, _) = debug("print 1 + 2;", "s\ns\ns\nc\n");
+
+    assert_eq!(said, "\
+test.lox:1: print 1 + 2;
+(debug) test.lox:1: evaluating 1 + 2
+(debug) test.lox:1: evaluating 1
+(debug) test.lox:1: evaluating 2
+(debug) ");
+}
+
+#[test]
+fn breakpoints_can_be_listed_and_deleted() {
+    let (_, said, _) = debug(SOURCE, "b 2\nb test.lox:4\nd 2\nbreakpoints\nb 9\nb other.lox:1\nq\n");
+
+    assert_eq!(said, "\
+test.lox:1: var a = 1;
+(debug) Breakpoint set at test.lox:2
+(debug) Breakpoint set at test.lox:4
+(debug) Breakpoint at test.lox:2 deleted
+(debug) test.lox:4: print a;
+(debug) Expected a line between 1 and 4, not '9'
+(debug) Only test.lox is being debugged
+(debug) ");
+}
+
+#[test]
+fn end_of_input_stops_the_program() {
+    let (result, _, printed) = debug(SOURCE, "");
+
+    assert_eq!(result, Some(RuntimeErrorKind::Stopped));
+    assert_eq!(printed, "");
+}
+
+#[test]
+fn runtime_errors_are_not_stops() {
+    let (result, _, _) = debug("print -nil;", "c\n");
+
+    assert_eq!(result, Some(RuntimeErrorKind::Error));
+}
+
+#[test]
+fn a_failed_run_doesnt_fail_the_next() {
+    let dir = std::env::temp_dir();
+    let broken = dir.join(format!("rlox-debugger-{}-broken.lox", std::process::id()));
+    let working = dir.join(format!("rlox-debugger-{}-working.lox", std::process::id()));
+    fs::write(&broken, "print @;\n").unwrap();
+    fs::write(&working, SOURCE).unwrap();
+
+    let run = |path: &std::path::Path| {
+        let input = Box::new(Cursor::new(b"c\n".to_vec()));
+        debugger::run_with(path.to_str().unwrap(), input, Box::new(io::sink()))
+    };
+
+    let codes = (run(&broken), run(&working));
+    fs::remove_file(&broken).unwrap();
+    fs::remove_file(&working).unwrap();
+
+    assert_eq!(codes, (EX_DATAERR, 0));
+}
@@ -0,0 +1,89 @@
+//! Checks the formatter's output, and that formatting formatted code changes
+//! nothing.
+
+use std::fs;
+use std::path::Path;
+use rlox::cli;
+use rlox::formatter::{format_program, MAX_WIDTH};
+use rlox::parser::Parser;
+use rlox::scanner::Scanner;
+
+fn format(source: &str) -> String {
+    let (tokens, comments) = Scanner::new(source).scan_tokens_and_comments();
+    let statements = Parser::new(tokens).parse_with_lines().unwrap_or_else(|_| panic!("{} doesn't parse", source));
+
+    format_program(&statements, &comments)
+}
+
+fn parses(source: &str) -> bool {
+    let (tokens, errors) = Scanner::new(source).scan_tokens_quietly();
+    errors.is_empty() && Parser::new(tokens).parse().is_ok()
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let long = format!("print {};", ["\"ünïcödé\""; 12].join(" + "));
+    let mut sources = vec![
+        String::from("print 1+2 ;var   a=3;\n\n\n\nprint a;"),
+        String::from("// leading\nprint 1; // trailing\n/* block\n   comment */\nprint 2;\n// at the end"),
+        String::from("var x = (true?1:2, 3);\nprint -(x) ** 2 ~/ 3 % 4 << 1 | 5 & 6 ^ ~7;"),
+        long,
+    ];
+
+    // Every script in the corpus that parses
+    for dir in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox")).unwrap() {
+        for script in fs::read_dir(dir.unwrap().path()).unwrap() {
+            let source = fs::read_to_string(script.unwrap().path()).unwrap();
+
+            if parses(&source) {
+                sources.push(source);
+            }
+        }
+    }
+
+    for source in &sources {
+        let once = format(source);
+        assert_eq!(format(&once), once, "formatting changed formatted code from\n{}", source);
+    }
+}
+
+#[test]
+fn canonical_style() {
+    assert_eq!(format("print 1+2 ;var   a=3;\n\n\n\nprint a;"), "print 1 + 2;\nvar a = 3;\n\nprint a;\n");
+}
+
+#[test]
+fn comments_are_kept() {
+    let source = "// leading\nprint 1;    // trailing\n\n/* block\n   comment */\nprint 2;\n// at the end\n";
+
+    assert_eq!(format(source), "// leading\nprint 1; // trailing\n\n/* block\n   comment */\nprint 2;\n// at the end\n");
+}
+
+#[test]
+fn widths_count_characters() {
+    // 60 characters, but more than 80 bytes
+    let text = "é".repeat(50);
+    let source = format!("print \"{}\" + 1;\n", text);
+
+    assert!(source.len() > MAX_WIDTH);
+    assert_eq!(format(&source), source);
+
+    // Past the limit in characters, the line is broken before the operator
+    let text = "é".repeat(75);
+    assert_eq!(format(&format!("print \"{}\" + 1;", text)), format!("print \"{}\"\n    + 1;\n", text));
+}
+
+#[test]
+fn check_passes_formatted_input() {
+    assert_eq!(cli::run(&args(&["fmt", "--check", "-e", "print 1 + 2;\n"])), 0);
+}
+
+#[test]
+fn check_fails_unformatted_input() {
+    assert_ne!(cli::run(&args(&["fmt", "--check", "-e", "print 1+2;"])), 0);
+    assert_ne!(cli::run(&args(&["fmt", "-e", "print 1+2;\n", "--check"])), 0);
+}
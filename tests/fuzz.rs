@@ -0,0 +1,75 @@
+//! Throws generated and pathological programs at every stage of the
+//! interpreter. None of them may panic.
+
+use rlox::fuzz::{self, Generator};
+
+fn all_stages(data: &[u8]) {
+    fuzz::scan(data);
+    fuzz::parse(data);
+    fuzz::interpret(data);
+}
+
+#[test]
+fn generated_programs() {
+    for seed in 0..2000 {
+        all_stages(Generator::new(seed).program().as_bytes());
+    }
+}
+
+#[test]
+fn generator_is_deterministic() {
+    assert_eq!(Generator::new(42).program(), Generator::new(42).program());
+    assert_ne!(Generator::new(1).program(), Generator::new(2).program());
+}
+
+#[test]
+fn odd_input() {
+    let inputs: &[&[u8]] = &[
+        b"",
+        b"\"",
+        b"//",
+        b"/",
+        b"1.",
+        b".1",
+        b"99999999999999999999999999999999999999999999999999",
+        b"\xff\xfe\x00",
+        b"print",
+        b"var",
+        b"var a = ;",
+        b")",
+        b"= = =",
+        b"\r\n\t",
+        "é \u{1F600} \u{200B}".as_bytes(),
+    ];
+
+    for input in inputs {
+        all_stages(input);
+    }
+}
+
+#[test]
+fn deep_nesting() {
+    let n = 100_000;
+
+    all_stages(format!("print {}1{};", "(".repeat(n), ")".repeat(n)).as_bytes());
+    all_stages(format!("print {}1;", "-".repeat(n)).as_bytes());
+    all_stages(format!("print {}1;", "!".repeat(n)).as_bytes());
+    all_stages(format!("print {}1;", "~".repeat(n)).as_bytes());
+    all_stages(format!("print {};", vec!["1"; n].join(" + ")).as_bytes());
+    all_stages(format!("print {};", vec!["1"; n].join(" ** -")).as_bytes());
+    all_stages(format!("var a; {}1;", "a = ".repeat(n)).as_bytes());
+    all_stages(format!("print {}1{};", "true ? ".repeat(n), " : 2".repeat(n)).as_bytes());
+    all_stages(format!("print {}1;", "false ? 1 : ".repeat(n)).as_bytes());
+    all_stages(format!("print {};", vec!["1"; n].join(", ")).as_bytes());
+}
+
+#[test]
+fn nesting_within_the_limit() {
+    // Deep but reasonable expressions still work, even on a test thread's smaller stack
+    let n = 95;
+
+    all_stages(format!("print {}1{};", "(".repeat(n), ")".repeat(n)).as_bytes());
+    all_stages(format!("print {};", vec!["1"; n].join(" + ")).as_bytes());
+    all_stages(format!("print {};", vec!["1"; n].join(" ** -")).as_bytes());
+    all_stages(format!("print {}1;", "-".repeat(n)).as_bytes());
+}
@@ -0,0 +1,145 @@
+//! Talks to the language server the way an editor would, with framed
+//! JSON-RPC messages piped through `serve`.
+
+use std::io::Cursor;
+use serde_json::{json, Value};
+use rlox::lsp::{read_message, serve, write_message};
+
+const URI: &str = "file:///test.lox";
+
+fn frame(messages: &[Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+
+    for message in messages {
+        write_message(&mut input, message).unwrap();
+    }
+
+    input
+}
+
+/// Serves `input` to the end, returning the exit code and every message sent back.
+fn session(input: Vec<u8>) -> (i32, Vec<Value>) {
+    let mut output = Vec::new();
+    let code = serve(&mut Cursor::new(input), &mut output).unwrap();
+
+    let mut replies = Vec::new();
+    let mut output = Cursor::new(output);
+
+    while let Some(message) = read_message(&mut output).unwrap() {
+        replies.push(message.unwrap());
+    }
+
+    (code, replies)
+}
+
+fn request(id: i64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn open(text: &str) -> Value {
+    notification("textDocument/didOpen", json!({
+        "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": text },
+    }))
+}
+
+fn at(line: u64, character: u64) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+fn reply(replies: &[Value], id: i64) -> &Value {
+    replies.iter().find(|r| r["id"] == id).unwrap_or_else(|| panic!("no reply to {}", id))
+}
+
+#[test]
+fn full_session() {
+    let input = frame(&[
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification("initialized", json!({})),
+        open("var a = 1;\nprint a + 2;\n"),
+        request(2, "textDocument/definition", at(1, 6)),
+        request(3, "textDocument/references", json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": 0, "character": 4 },
+            "context": { "includeDeclaration": true },
+        })),
+        request(4, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    let (code, replies) = session(input);
+
+    assert_eq!(code, 0);
+    assert_eq!(reply(&replies, 1)["result"]["capabilities"]["definitionProvider"], true);
+
+    let diagnostics = replies.iter().find(|r| r["method"] == "textDocument/publishDiagnostics").unwrap();
+    assert_eq!(diagnostics["params"], json!({ "uri": URI, "diagnostics": [] }));
+
+    assert_eq!(reply(&replies, 2)["result"], json!([{
+        "uri": URI,
+        "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 5 } },
+    }]));
+
+    assert_eq!(reply(&replies, 3)["result"], json!([
+        { "uri": URI, "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 5 } } },
+        { "uri": URI, "range": { "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 7 } } },
+    ]));
+
+    assert_eq!(reply(&replies, 4)["result"], Value::Null);
+}
+
+#[test]
+fn diagnostics_for_errors() {
+    let (_, replies) = session(frame(&[open("print 1 +;\nprint @;\n")]));
+
+    let diagnostics = replies[0]["params"]["diagnostics"].as_array().unwrap();
+    let lines: Vec<&Value> = diagnostics.iter().map(|d| &d["range"]["start"]["line"]).collect();
+
+    // The scanner's errors come first, then the parser's, which also
+    // trips over the `print` whose expression was skipped
+    assert_eq!(lines, vec![&json!(1), &json!(0), &json!(1)]);
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let (code, _) = session(frame(&[notification("exit", Value::Null)]));
+    assert_eq!(code, 1);
+
+    // Closing the input without exiting fails too
+    let (code, _) = session(frame(&[request(1, "initialize", json!({}))]));
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn malformed_json_is_answered_and_serving_continues() {
+    let mut input = b"Content-Length: 9\r\n\r\n{\"id\": 1,".to_vec();
+    input.extend(frame(&[request(2, "shutdown", Value::Null), notification("exit", Value::Null)]));
+
+    let (code, replies) = session(input);
+
+    assert_eq!(code, 0);
+    assert_eq!(replies[0]["id"], Value::Null);
+    assert_eq!(replies[0]["error"]["code"], -32700);
+    assert_eq!(reply(&replies, 2)["result"], Value::Null);
+}
+
+#[test]
+fn oversized_message_is_refused() {
+    let mut input = b"Content-Length: 18446744073709551615\r\n\r\n".to_vec();
+    input.extend(b"{}");
+
+    let (code, replies) = session(input);
+
+    assert_eq!(code, 1);
+    assert_eq!(replies[0]["error"]["code"], -32700);
+}
+
+#[test]
+fn unknown_methods_are_errors() {
+    let (_, replies) = session(frame(&[request(7, "textDocument/rename", at(0, 0))]));
+
+    assert_eq!(reply(&replies, 7)["error"]["code"], -32601);
+}
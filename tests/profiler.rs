@@ -0,0 +1,85 @@
+//! Profiles small programs and checks the folded stacks they leave behind.
+//! Times vary between runs, so only the stacks are compared exactly.
+
+use std::fs;
+use std::path::PathBuf;
+use rlox::cli;
+use rlox::lox::EX_DATAERR;
+use rlox::profiler;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rlox-profiler-{}-{}.folded", std::process::id(), name))
+}
+
+/// Splits folded output into its stacks and their times in microseconds.
+fn stacks(folded: &str) -> Vec<(&str, u128)> {
+    folded.lines()
+        .map(|l| {
+            let (stack, time) = l.rsplit_once(' ').unwrap_or_else(|| panic!("no time in '{}'", l));
+            (stack, time.parse().unwrap_or_else(|_| panic!("'{}' isn't a time", time)))
+        })
+        .collect()
+}
+
+#[test]
+fn folded_stacks_for_a_known_script() {
+    let path = temp_path("known");
+    let code = profiler::run("var a = 1;\nvar b = 2;\n\nprint a + b;\n", path.to_str().unwrap());
+
+    let folded = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(code, 0);
+
+    let names: Vec<&str> = stacks(&folded).into_iter().map(|(s, _)| s).collect();
+    assert_eq!(names, vec!["<script>;line 1", "<script>;line 2", "<script>;line 4"]);
+}
+
+#[test]
+fn time_on_one_line_is_folded_together() {
+    let path = temp_path("shared");
+    let code = profiler::run("var i = 0;\ni = i + 1; i = i + 1; i = i + 1;\n", path.to_str().unwrap());
+
+    let folded = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(code, 0);
+
+    // The statements sharing line 2 share its stack
+    let names: Vec<&str> = stacks(&folded).into_iter().map(|(s, _)| s).collect();
+    assert_eq!(names, vec!["<script>;line 1", "<script>;line 2"]);
+}
+
+#[test]
+fn output_option_sets_the_path() {
+    let path = temp_path("cli");
+    let args: Vec<String> = ["run", "--profile", "-e", "print 1;", "--output", path.to_str().unwrap()]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+
+    assert_eq!(cli::run(&args), 0);
+
+    let folded = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stacks(&folded).len(), 1);
+    assert!(folded.starts_with("<script>;line 1 "));
+}
+
+#[test]
+fn output_needs_a_report_to_write() {
+    let args: Vec<String> = ["run", "-e", "print 1;", "--output", "x"].iter().map(|a| a.to_string()).collect();
+
+    assert_ne!(cli::run(&args), 0);
+}
+
+#[test]
+fn a_failed_run_doesnt_fail_the_next() {
+    let path = temp_path("after-failure");
+
+    assert_eq!(profiler::run("print @;", path.to_str().unwrap()), EX_DATAERR);
+    assert_eq!(profiler::run("print 1;", path.to_str().unwrap()), 0);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,285 @@
+//! Types at the REPL the way a user would, through the `rlox` binary with its
+//! input piped in, and checks what comes back after each prompt.
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use rustyline::completion::Completer;
+use rustyline::history::DefaultHistory;
+use rustyline::Context;
+use rlox::cli;
+use rlox::lox::{Lox, EX_DATAERR, EX_NOINPUT, EX_SOFTWARE, EX_USAGE};
+use rlox::repl::{Command as ReplCommand, LoxHelper};
+
+/// Feeds `input` to a REPL session to the end, returning the exit code, what
+/// came back after each prompt, and what went to stderr.
+fn session(input: &str) -> (i32, Vec<String>, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Every line read is preceded by a prompt, so the first answer is before any line
+    let answers = stdout.split("> ").skip(1).map(String::from).collect();
+
+    (output.status.code().unwrap_or(-1), answers, String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn declarations_persist_between_lines() {
+    let (_, answers, _) = session("var a = 1;\nvar b = a * 10;\nprint a + b;\n");
+
+    assert_eq!(answers, vec!["", "", "11\n", ""]);
+}
+
+#[test]
+fn bare_expressions_are_echoed() {
+    let (_, answers, _) = session("var a = 1;\na + 1\na = 2;\nprint a;\n");
+
+    // With or without a `;`, an expression's value is shown; `print` shows it once
+    assert_eq!(answers, vec!["", "2\n", "2\n", "2\n", ""]);
+}
+
+#[test]
+fn errors_keep_the_session_going() {
+    let (code, answers, _) = session("var a = 1;\nprint -nil;\n1 +;\na\n");
+
+    assert_eq!(code, 0);
+    assert_eq!(answers[1], "Operand must be a number\n[line 1]\n");
+    assert!(answers[2].starts_with("Parsing error"), "{:?}", answers);
+    assert_eq!(answers[3], "1\n");
+}
+
+#[test]
+fn commands_parse() {
+    assert!(ReplCommand::parse("print 1;").is_none());
+    assert!(matches!(ReplCommand::parse(":help"), Some(Ok(ReplCommand::Help))));
+    assert!(matches!(ReplCommand::parse("  :quit  "), Some(Ok(ReplCommand::Quit))));
+    assert!(matches!(ReplCommand::parse(":load  dir/a file.lox "), Some(Ok(ReplCommand::Load("dir/a file.lox")))));
+    assert!(matches!(ReplCommand::parse(":time 1 + 2"), Some(Ok(ReplCommand::Time("1 + 2")))));
+
+    assert_eq!(ReplCommand::parse(":reset now").unwrap().err().unwrap(), ":reset takes no arguments");
+    assert_eq!(ReplCommand::parse(":ast").unwrap().err().unwrap(), ":ast needs an argument");
+    assert_eq!(ReplCommand::parse(":").unwrap().err().unwrap(), "Unknown command :. Type :help for a list of commands.");
+}
+
+#[test]
+fn env_and_reset() {
+    let (_, answers, _) = session(":env\nvar a = 1;\nvar b = \"two\";\n:env\n:reset\n:env\na\n");
+
+    assert_eq!(answers[0], "");
+    assert_eq!(answers[3], "a = 1\nb = two\n");
+    assert_eq!(answers[5], "");
+    assert!(answers[6].starts_with("Undefined variable 'a'"), "{:?}", answers);
+}
+
+#[test]
+fn load_runs_a_script_into_the_session() {
+    let path = std::env::temp_dir().join(format!("rlox-repl-{}.lox", std::process::id()));
+    std::fs::write(&path, "var loaded = 42;\nprint \"loading\";\n").unwrap();
+
+    let (_, answers, _) = session(&format!(":load {}\nloaded + 1\n:load {}.missing\n", path.display(), path.display()));
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(answers[0], "loading\n");
+    assert_eq!(answers[1], "43\n");
+    assert!(answers[2].starts_with("Could not read file"), "{:?}", answers);
+}
+
+#[test]
+fn tokens_ast_and_time() {
+    let (_, answers, _) = session(":tokens 1+2\n:ast 1+2*3\n:ast var a = 1;\n:time 1 + 1\n");
+
+    assert_eq!(answers[0], "NUMBER 1\nPLUS +\nNUMBER 2\nEOF \n");
+    assert_eq!(answers[1], "(+ 1 (* 2 3))\n");
+    assert!(answers[2].starts_with("(var a"), "{:?}", answers);
+    assert!(answers[3].starts_with("2\nTook "), "{:?}", answers);
+}
+
+#[test]
+fn help_lists_the_commands() {
+    let (_, answers, _) = session(":help\n");
+
+    // The help's `<file> ` looks like a prompt, so look for the commands in all of it
+    let help = answers.concat();
+
+    for command in &[":help", ":quit", ":load", ":reset", ":env", ":tokens", ":ast", ":time"] {
+        assert!(help.contains(command), "{} isn't in\n{}", command, help);
+    }
+}
+
+#[test]
+fn quit_ends_the_session() {
+    let (code, answers, _) = session("print 1;\n:quit\nprint \"unreachable\";\n");
+
+    assert_eq!(code, 0);
+    assert_eq!(answers, vec!["1\n", ""]);
+}
+
+#[test]
+fn bad_commands_are_explained() {
+    let (_, answers, _) = session(":bogus\n:env x\n");
+
+    assert_eq!(answers, vec!["Unknown command :bogus. Type :help for a list of commands.\n", ":env takes no arguments\n", ""]);
+}
+
+/// What the line editor would offer for tab at the end of `line`.
+fn complete(helper: &LoxHelper, line: &str) -> (usize, Vec<String>) {
+    let history = DefaultHistory::new();
+    helper.complete(line, line.len(), &Context::new(&history)).unwrap()
+}
+
+#[test]
+fn completion_offers_keywords_globals_and_commands() {
+    let mut helper = LoxHelper::default();
+    helper.set_globals(vec![String::from("total"), String::from("value"), String::from("variance")]);
+
+    assert_eq!(complete(&helper, "pr"), (0, vec![String::from("print")]));
+    assert_eq!(complete(&helper, "print t"), (6, vec![String::from("this"), String::from("total"), String::from("true")]));
+    assert_eq!(complete(&helper, "a = va"), (4, vec![String::from("value"), String::from("var"), String::from("variance")]));
+    assert_eq!(complete(&helper, ":t"), (0, vec![String::from(":time"), String::from(":tokens")]));
+    assert_eq!(complete(&helper, "print "), (6, vec![]));
+}
+
+#[test]
+fn completion_handles_non_ascii_text() {
+    let mut helper = LoxHelper::default();
+    helper.set_globals(vec![String::from("naïve"), String::from("naïveté"), String::from("π")]);
+
+    // Inside a string there's a word to complete, but nothing matches it
+    assert_eq!(complete(&helper, "print \"café"), (7, vec![]));
+    assert_eq!(complete(&helper, "print \"é"), (7, vec![]));
+
+    assert_eq!(complete(&helper, "var naï"), (4, vec![String::from("naïve"), String::from("naïveté")]));
+    assert_eq!(complete(&helper, "print 2 * π"), (10, vec![String::from("π")]));
+}
+
+#[test]
+fn piped_input_is_read_plainly() {
+    // Not a terminal, so there's no line editing to swallow control characters
+    let (_, answers, _) = session("\"a\tb\"\n");
+
+    assert_eq!(answers, vec!["a\tb\n", ""]);
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+/// Runs `rlox` with `args` and `input` on stdin, returning the exit code and
+/// what went to stdout and stderr.
+fn rlox(args: &[&str], input: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+
+    (output.status.code().unwrap_or(-1), text(&output.stdout), text(&output.stderr))
+}
+
+#[test]
+fn end_of_input_ends_the_session() {
+    assert_eq!(session(""), (0, vec![String::new()], String::new()));
+
+    // A last line without a newline still runs
+    let (code, answers, _) = session("print 1;");
+    assert_eq!(code, 0);
+    assert_eq!(answers, vec!["1\n", ""]);
+}
+
+#[test]
+fn exit_codes() {
+    // The error flags last as long as the thread, so these run one after another
+    assert_eq!(Lox::run_source("print 1;"), 0);
+    assert_eq!(Lox::run_source("print 1 +;"), EX_DATAERR);
+    assert_eq!(Lox::run_source("print @;"), EX_DATAERR);
+    assert_eq!(Lox::run_source("print -nil;"), EX_SOFTWARE);
+
+    // An error in one run doesn't fail the next
+    assert_eq!(Lox::run_source("print 2;"), 0);
+}
+
+#[test]
+fn commands_after_a_failure_pass() {
+    for command in &["tokens", "ast", "check", "fmt"] {
+        assert_eq!(cli::run(&args(&[command, "-e", "print @;"])), EX_DATAERR, "rlox {}", command);
+        assert_eq!(cli::run(&args(&[command, "-e", "print 1;\n"])), 0, "rlox {} after a failure", command);
+    }
+}
+
+#[test]
+fn failures_exit_without_panicking() {
+    let missing = std::env::temp_dir().join("rlox-repl-missing.lox");
+
+    for (args, code) in &[
+        (vec![missing.to_str().unwrap()], EX_NOINPUT),
+        (vec!["run", "-e", "print 1 +;"], EX_DATAERR),
+        (vec!["run", "-e", "print -nil;"], EX_SOFTWARE),
+        (vec!["run", "-e"], EX_USAGE),
+        (vec!["run", "-e", "print 1;"], 0),
+    ] {
+        let (actual, _, stderr) = rlox(args, "");
+
+        assert_eq!(actual, *code, "rlox {:?}: {}", args, stderr);
+        assert!(!stderr.contains("panicked"), "rlox {:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn commands_print_what_they_promise() {
+    assert_eq!(rlox(&["tokens", "-e", "print 1;"], ""), (0, String::from("PRINT print\nNUMBER 1\nSEMICOLON ;\nEOF \n"), String::new()));
+    assert_eq!(rlox(&["ast", "-e", "print 1 + 2;"], ""), (0, String::from("(print (+ 1 2))\n"), String::new()));
+    assert_eq!(rlox(&["check", "-e", "print 1;"], ""), (0, String::new(), String::new()));
+
+    // `-` reads the program from stdin
+    assert_eq!(rlox(&["-"], "print 1 + 2;"), (0, String::from("3\n"), String::new()));
+    assert_eq!(rlox(&["fmt", "-"], "print 1+2;"), (0, String::from("print 1 + 2;\n"), String::new()));
+    assert_eq!(rlox(&["-e", "print \"one-liner\";"], ""), (0, String::from("one-liner\n"), String::new()));
+
+    let (code, stdout, stderr) = rlox(&["check", "-"], "print 1 +;\nprint @;\n");
+    assert_eq!((code, stdout.as_str()), (EX_DATAERR, ""));
+    assert!(stderr.contains("Unexpected character"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn closed_output_is_not_a_panic() {
+    let source = "print 1;\n".repeat(50_000);
+
+    for command in &["tokens", "ast", "fmt"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+            .args([command, "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        child.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+
+        // Like `head -1`: read a little, then stop listening
+        let mut first = [0; 16];
+        child.stdout.take().unwrap().read_exact(&mut first).unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(output.status.code(), Some(0), "rlox {}: {}", command, stderr);
+        assert_eq!(stderr, "", "rlox {}", command);
+    }
+}